@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+
+/// Most entries returned by a single audit log query, newest first
+pub const MAX_AUDIT_RESULTS: u32 = 1000;
+
+/// Longest payload summary stored with an entry, in characters
+pub const MAX_SUMMARY_LEN: usize = 200;
+
+/// Age in seconds after which entries are deleted from the audit log
+pub const AUDIT_RETENTION: u64 = 90 * 24 * 60 * 60;
+
+/// Record of one packet handled by auth_svc, stored in the audit_log table
+/// username is empty and role is 0 for requests without a valid token
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuditEntry {
+    // unix time in seconds
+    pub time: u64,
+    pub username: String,
+    pub role: u8,
+    pub client_addr: String,
+    pub cmd_type: u8,
+    // payload with passwords and tokens left out
+    pub summary: String,
+    // "OK", or the message the request was refused or failed with
+    pub outcome: String,
+}
+
+/// Filter for querying the audit log, every field left out matches all entries
+/// from and to are unix times in seconds and both inclusive
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct AuditQuery {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub username: Option<String>,
+    pub cmd_type: Option<u8>,
+}
@@ -1,111 +1,441 @@
-use anyhow::Result;
-use boringauth::pass::is_valid;
+use anyhow::{Context, Result};
+use boringauth::pass::{derive_password, is_valid};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Reverse, collections::HashMap, fs, io::Write, net::SocketAddr, path::PathBuf,
+    sync::OnceLock, time::Duration,
+};
 use tokio::sync::{mpsc::Receiver, mpsc::Sender};
 
-use crate::user::User;
-use shared::{login::LoginCredentials, remote_conn_packet::RemotePacket};
+use crate::audit::{AuditEntry, MAX_SUMMARY_LEN};
+use crate::clock::unix_time;
+use crate::role::{Policy, NO_ROLE};
+use crate::user::{Identity, User};
+use shared::{login::LoginCredentials, remote_conn_packet::RemotePacket, user::UserRaw};
 
-const SECRET_KEY: &[u8; 8] = b"openlink";
+/// File the generated signing secret is persisted in when none is configured
+pub const DEFAULT_SECRET_FILE: &str = "openlink.key";
+
+/// Default lifetime of the access token sent with every command
+pub const DEFAULT_ACCESS_LIFETIME: Duration = Duration::from_secs(15 * 60);
+
+/// Default lifetime of the refresh token used to get a new access token
+pub const DEFAULT_REFRESH_LIFETIME: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// Commands accepted without an access token, login, refresh and logout
+/// Logout checks the token itself so an expired access token can still end its session
+const PUBLIC_CMDS: [u8; 3] = [1, 2, 4];
+
+/// Database commands that remove a user or change their group or password,
+/// every session of that user is revoked after each
+const USER_CHANGE_CMDS: std::ops::RangeInclusive<u8> = 163..=165;
+
+/// Database commands that change roles or permissions, the policy is reloaded after each
+const POLICY_CMDS: std::ops::RangeInclusive<u8> = 167..=170;
+
+/// Failed logins allowed for one username within FAILURE_WINDOW before it is locked out
+pub const MAX_USER_FAILURES: u32 = 5;
+
+/// Failed logins allowed from one client address within FAILURE_WINDOW before it is locked out
+/// Higher than the per-user limit since several operators may share an address
+pub const MAX_ADDR_FAILURES: u32 = 20;
+
+/// Window failed logins are counted over, in seconds
+pub const FAILURE_WINDOW: u64 = 5 * 60;
+
+/// How long a username or client address stays locked out, in seconds
+pub const LOCKOUT_DURATION: u64 = 15 * 60;
+
+/// The one answer to a failed login, so it never tells whether the username exists
+const LOGIN_FAILED: &str = "Invalid username or password";
+
+/// Length in bytes of a generated signing secret
+const SECRET_LEN: usize = 32;
+
+/// Access tokens authorize commands, refresh tokens are only accepted by the refresh command
+#[derive(Serialize, Deserialize, PartialEq)]
+enum TokenKind {
+    Access,
+    Refresh,
+}
 
 #[derive(Serialize, Deserialize)]
 struct Claims {
-    exp: usize,
+    exp: u64,
+    iat: u64,
     ugroup: u8,
+    username: String,
+    kind: TokenKind,
+    // id of the session the token belongs to, tokens of a revoked session are rejected
+    sid: String,
+}
+
+/// Logged in client, created at login and ended by logout, revocation or refresh token expiry
+/// Sessions are only kept in memory, so every client logs in again after the pod restarts
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Session {
+    pub id: String,
+    pub username: String,
+    pub ugroup: u8,
+    pub client_addr: String,
+    // unix times in seconds
+    pub created: u64,
+    pub last_activity: u64,
+    // when the latest refresh token expires, the session ends unless refreshed before then
+    pub expires: u64,
+}
+
+/// Key and lifetimes used to sign and check tokens
+///
+/// The secret comes from OPENLINK_JWT_SECRET if set, otherwise it is read from
+/// OPENLINK_JWT_SECRET_FILE (default openlink.key), which is generated on first boot.
+/// Lifetimes in seconds can be set with OPENLINK_ACCESS_LIFETIME and OPENLINK_REFRESH_LIFETIME.
+pub struct AuthConfig {
+    pub secret: Vec<u8>,
+    // where the secret is persisted, None if it was given directly by configuration
+    pub secret_file: Option<PathBuf>,
+    pub access_lifetime: Duration,
+    pub refresh_lifetime: Duration,
+}
+
+impl AuthConfig {
+    pub fn from_env() -> Result<Self> {
+        let (secret, secret_file) = match std::env::var("OPENLINK_JWT_SECRET") {
+            Ok(secret) if !secret.is_empty() => (secret.into_bytes(), None),
+            _ => {
+                let path = PathBuf::from(
+                    std::env::var("OPENLINK_JWT_SECRET_FILE")
+                        .unwrap_or_else(|_| s!(DEFAULT_SECRET_FILE)),
+                );
+                (load_or_create_secret(&path)?, Some(path))
+            }
+        };
+
+        Ok(Self {
+            secret,
+            secret_file,
+            access_lifetime: lifetime_from_env(
+                "OPENLINK_ACCESS_LIFETIME",
+                DEFAULT_ACCESS_LIFETIME,
+            )?,
+            refresh_lifetime: lifetime_from_env(
+                "OPENLINK_REFRESH_LIFETIME",
+                DEFAULT_REFRESH_LIFETIME,
+            )?,
+        })
+    }
+}
+
+fn lifetime_from_env(var: &str, default: Duration) -> Result<Duration> {
+    match std::env::var(var) {
+        Ok(secs) => {
+            Ok(Duration::from_secs(secs.parse().with_context(|| {
+                format!("{} must be a number of seconds", var)
+            })?))
+        }
+        Err(_) => Ok(default),
+    }
+}
+
+/// Read the persisted signing secret, generating and saving a new one if the file does not exist
+fn load_or_create_secret(path: &PathBuf) -> Result<Vec<u8>> {
+    match fs::read(path) {
+        Ok(secret) if secret.len() >= SECRET_LEN => Ok(secret),
+        Ok(_) => anyhow::bail!("signing secret in {} is too short", path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            println!("auth_svc: generating signing secret {}", path.display());
+            create_secret(path)
+        }
+        Err(e) => {
+            Err(e).with_context(|| format!("failed to read signing secret {}", path.display()))
+        }
+    }
+}
+
+/// Generate a random signing secret and persist it, readable only by the owner
+fn create_secret(path: &PathBuf) -> Result<Vec<u8>> {
+    let secret: Vec<u8> = (0..SECRET_LEN).map(|_| rand::thread_rng().gen()).collect();
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(&secret))
+        .with_context(|| format!("failed to save signing secret {}", path.display()))?;
+
+    Ok(secret)
+}
+
+/// Summarize a packet's payload for the audit log, leaving out passwords and tokens
+fn summarize(pkt: &RemotePacket) -> String {
+    let summary = match pkt.cmd_type {
+        // login credentials, only the username is kept
+        1 => pkt
+            .payload
+            .first()
+            .and_then(|c| serde_json::from_str::<LoginCredentials>(c).ok())
+            .map(|c| c.username)
+            .unwrap_or_default(),
+        // refresh tokens
+        2 | 4 => s!(""),
+        // new users and passwords, only the name and group are kept
+        160 | 165 => pkt
+            .payload
+            .first()
+            .and_then(|u| serde_json::from_str::<UserRaw>(u).ok())
+            .map(|u| format!("{}, ugroup {}", u.name, u.ugroup))
+            .unwrap_or_default(),
+        _ => pkt.payload.join(", "),
+    };
+
+    summary.chars().take(MAX_SUMMARY_LEN).collect()
+}
+
+/// Identity of the user a verified token was issued to
+fn identity(claims: Claims) -> Identity {
+    Identity {
+        username: claims.username,
+        ugroup: claims.ugroup,
+        sid: claims.sid,
+    }
+}
+
+/// Hash checked against for usernames that do not exist, derived once on first use
+fn dummy_hash() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+    HASH.get_or_init(|| derive_password("no such user").unwrap_or_default())
+}
+
+/// Failed logins counted against a single username or client address
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct LoginAttempts {
+    pub failures: u32,
+    // unix times in seconds
+    pub first_failure: u64,
+    pub locked_until: u64,
+}
+
+impl LoginAttempts {
+    fn locked(&self, now: u64) -> bool {
+        self.locked_until > now
+    }
+
+    /// Count a failure, locking out once limit failures fall within FAILURE_WINDOW
+    /// Returns true if this failure started a lockout
+    fn fail(&mut self, limit: u32, now: u64) -> bool {
+        if now.saturating_sub(self.first_failure) > FAILURE_WINDOW {
+            self.failures = 0;
+            self.first_failure = now;
+        }
+        self.failures += 1;
+
+        if self.failures >= limit && !self.locked(now) {
+            self.locked_until = now + LOCKOUT_DURATION;
+            return true;
+        }
+
+        false
+    }
+
+    fn stale(&self, now: u64) -> bool {
+        !self.locked(now) && now.saturating_sub(self.first_failure) > FAILURE_WINDOW
+    }
+}
+
+/// Failed logins per username and per client address, kept in memory
+/// Usernames are counted whether or not they exist, so lockouts do not reveal which ones do
+#[derive(Serialize, Deserialize, Default)]
+pub struct LoginThrottle {
+    pub users: HashMap<String, LoginAttempts>,
+    pub addrs: HashMap<String, LoginAttempts>,
+}
+
+impl LoginThrottle {
+    fn locked(&self, user: &str, addr: &str, now: u64) -> bool {
+        self.users.get(user).is_some_and(|a| a.locked(now))
+            || self.addrs.get(addr).is_some_and(|a| a.locked(now))
+    }
+
+    fn fail(&mut self, user: &str, addr: &str, now: u64) {
+        if self
+            .users
+            .entry(s!(user))
+            .or_default()
+            .fail(MAX_USER_FAILURES, now)
+        {
+            println!("auth_svc: user {} locked out after failed logins", user);
+        }
+        if self
+            .addrs
+            .entry(s!(addr))
+            .or_default()
+            .fail(MAX_ADDR_FAILURES, now)
+        {
+            println!("auth_svc: address {} locked out after failed logins", addr);
+        }
+    }
+
+    fn succeed(&mut self, user: &str, addr: &str) {
+        self.users.remove(user);
+        self.addrs.remove(addr);
+    }
+
+    /// Forget failures that are outside the window and not holding a lockout
+    fn purge(&mut self, now: u64) {
+        self.users.retain(|_, a| !a.stale(now));
+        self.addrs.retain(|_, a| !a.stale(now));
+    }
 }
 
 pub struct AuthSvc {
-    pub rx_remote: Receiver<RemotePacket>,
+    pub config: AuthConfig,
+    // roles and the commands each may use, loaded from the database
+    pub policy: Policy,
+    // active sessions, keyed by session id
+    pub sessions: HashMap<String, Session>,
+    // failed logins per username and client address
+    pub throttle: LoginThrottle,
+
+    // every request arrives with the address of the client that sent it
+    pub rx_remote: Receiver<(RemotePacket, SocketAddr)>,
     pub tx_remote: Sender<RemotePacket>,
 
+    // forwarded packets go with the identity of their sender
     pub rx_link: Receiver<RemotePacket>,
-    pub tx_link: Sender<RemotePacket>,
+    pub tx_link: Sender<(RemotePacket, Identity)>,
 
     pub rx_ctrl: Receiver<RemotePacket>,
-    pub tx_ctrl: Sender<RemotePacket>,
+    pub tx_ctrl: Sender<(RemotePacket, Identity)>,
 
     pub rx_data: Receiver<RemotePacket>,
-    pub tx_data: Sender<RemotePacket>,
+    pub tx_data: Sender<(RemotePacket, Identity)>,
+    // a record of every packet handled is stored by database_svc
+    pub tx_audit: Sender<AuditEntry>,
 
     pub rx_tele: Receiver<RemotePacket>,
-    pub tx_tele: Sender<RemotePacket>,
+    pub tx_tele: Sender<(RemotePacket, Identity)>,
 }
 
 impl AuthSvc {
     /// Main service task for auth service
     /// Also serves as command parser since all commands require authentication
+    /// Each command is only forwarded if the policy allows the sender's role to use it
+    /// Every packet is recorded in the audit log along with who sent it and its outcome
     pub async fn run(mut self) -> Result<()> {
         println!("auth_svc running");
 
-        while let Some(pkt) = self.rx_remote.recv().await {
+        // what each role may do is kept in the database so it can be edited without recompiling
+        self.load_policy().await;
+
+        while let Some((pkt, addr)) = self.rx_remote.recv().await {
             // send packet to associated service based on cmd_type field range
             println!("Packet of type {} received", pkt.cmd_type);
-            let resp: RemotePacket = match pkt.cmd_type {
-                0..=31 => {
-                    // auth service command handling
-                    self.auth_handler(&pkt).await.unwrap()
-                }
-                32..=63 => {
-                    // link service command handling
-                    // restrict to only admin and software team accounts
-                    let ugroup = self.check_token(pkt.token.clone());
-                    if ugroup == 0 || ugroup == 1 {
-                        RemotePacket::new(0, vec![s!("Not authorized")])
-                    } else {
-                        if let Err(e) = self.tx_link.send(pkt).await {
+            let cmd_type = pkt.cmd_type;
+            // taken before handling since logout ends the session and pkt is passed on
+            let mut identity = self.identify(&pkt);
+            let summary = summarize(&pkt);
+
+            let resp: RemotePacket = if PUBLIC_CMDS.contains(&cmd_type) {
+                // login and refresh are how a token is obtained, so they need none
+                self.auth_handler(&pkt, addr).await.unwrap()
+            } else if !self.policy.allows(identity.ugroup, cmd_type) {
+                RemotePacket::new(0, vec![s!("Not authorized")])
+            } else {
+                match cmd_type {
+                    0..=31 => {
+                        // auth service command handling
+                        self.auth_handler(&pkt, addr).await.unwrap()
+                    }
+                    32..=63 => {
+                        // link service command handling
+                        if let Err(e) = self.tx_link.send((pkt, identity.clone())).await {
                             eprintln!("auth->link failed: {}", e);
                         }
                         self.rx_link.recv().await.unwrap()
                     }
-                }
-                64..=127 => {
-                    // control service command handling
-                    // restrict to only admin and mission control accounts
-                    let ugroup = self.check_token(pkt.token.clone());
-                    if ugroup == 0 || ugroup == 2 {
-                        RemotePacket::new(0, vec![s!("Not authorized")])
-                    } else {
-                        if let Err(e) = self.tx_ctrl.send(pkt).await {
+                    64..=127 => {
+                        // control service command handling
+                        if let Err(e) = self.tx_ctrl.send((pkt, identity.clone())).await {
                             eprintln!("auth->launch failed: {}", e);
                         }
                         self.rx_ctrl.recv().await.unwrap()
                     }
-                }
-                128..=159 => {
-                    // telemetry service command handling
-                    // all authenticated users can access telemetry
-                    if self.check_token(pkt.token.clone()) == 0 {
-                        RemotePacket::new(0, vec![s!("Not authorized")])
-                    } else {
-                        if let Err(e) = self.tx_tele.send(pkt).await {
+                    128..=159 => {
+                        // telemetry service command handling
+                        if let Err(e) = self.tx_tele.send((pkt, identity.clone())).await {
                             eprintln!("auth->tele failed: {}", e);
                         }
                         self.rx_tele.recv().await.unwrap()
                     }
-                }
-                160..=195 => {
-                    // database service command handling
-                    // restrict to only admin accounts
-                    if self.check_token(pkt.token.clone()) != 255 {
-                        RemotePacket::new(0, vec![s!("Not authorized")])
-                    } else {
-                        if let Err(e) = self.tx_data.send(pkt).await {
+                    160..=195 => {
+                        // database service command handling
+                        let changed_user = match cmd_type {
+                            163..=165 => changed_user(&pkt),
+                            _ => None,
+                        };
+                        if let Err(e) = self.tx_data.send((pkt, identity.clone())).await {
                             eprintln!("auth->database failed: {}", e);
                         }
-                        self.rx_data.recv().await.unwrap()
+                        let resp = self.rx_data.recv().await.unwrap();
+
+                        // old tokens must not outlive a removal, downgrade or password change
+                        if USER_CHANGE_CMDS.contains(&cmd_type) && resp.cmd_type != 0 {
+                            if let Some(name) = changed_user {
+                                self.revoke_user(&name);
+                            }
+                        }
+
+                        // pick up role and permission changes straight away
+                        if POLICY_CMDS.contains(&cmd_type) && resp.cmd_type != 0 {
+                            self.load_policy().await;
+                        }
+
+                        resp
+                    }
+                    196..=227 => {
+                        // extra?
+                        //
+                        pkt
+                    }
+                    228..=255 => {
+                        // extra?
+                        pkt
                     }
                 }
-                196..=227 => {
-                    // extra?
-                    //
-                    pkt
-                }
-                228..=255 => {
-                    // extra?
-                    pkt
-                }
             };
 
+            // a login only has a role once it has succeeded
+            if cmd_type == 1 && resp.cmd_type == 1 {
+                identity.ugroup = resp
+                    .payload
+                    .get(1)
+                    .and_then(|ugroup| ugroup.parse().ok())
+                    .unwrap_or(NO_ROLE);
+            }
+
+            let entry = AuditEntry {
+                time: unix_time(),
+                username: identity.username,
+                role: identity.ugroup,
+                client_addr: s!(addr),
+                cmd_type,
+                summary,
+                outcome: match resp.cmd_type {
+                    0 => resp
+                        .payload
+                        .first()
+                        .cloned()
+                        .unwrap_or_else(|| s!("Failed")),
+                    _ => s!("OK"),
+                },
+            };
+            if let Err(e) = self.tx_audit.send(entry).await {
+                eprintln!("auth->database failed: {}", e);
+            }
+
             // send the modified packet back to remote_conn_svc
             if let Err(e) = self.tx_remote.send(resp).await {
                 eprintln!("auth->remote failed: {}", e);
@@ -118,76 +448,433 @@ impl AuthSvc {
     }
 
     /// Handler for auth service defined ranges of cmd_type
-    async fn auth_handler(&mut self, pkt: &RemotePacket) -> Result<RemotePacket> {
+    async fn auth_handler(&mut self, pkt: &RemotePacket, addr: SocketAddr) -> Result<RemotePacket> {
         let resp = match pkt.cmd_type {
-            1 => self.login(&pkt).await,
+            1 => self.login(pkt, addr).await,
+            2 => self.refresh(pkt),
+            3 => self.rotate_secret(),
+            4 => self.logout(pkt),
+            5 => self.list_sessions(),
+            6 => self.revoke_sessions(pkt),
+            7 => self.list_lockouts(),
+            8 => self.clear_lockout(pkt),
             _ => RemotePacket::new(0, vec![s!("Command not implemented")]),
         };
 
         Ok(resp)
     }
 
-    /// Check for user token and return the usergroup
-    fn check_token(&self, token: String) -> u8 {
-        let validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS256);
+    /// Query data_svc for every role and permission
+    /// The previous policy is kept if the database cannot be read
+    async fn load_policy(&mut self) {
+        if let Err(e) = self
+            .tx_data
+            .send((RemotePacket::new(166, vec![]), Identity::default()))
+            .await
+        {
+            eprintln!("auth->database failed: {}", e);
+            return;
+        }
 
-        match jsonwebtoken::decode::<Claims>(
-            &token,
-            &jsonwebtoken::DecodingKey::from_secret(SECRET_KEY),
+        let resp = self.rx_data.recv().await.unwrap();
+        match resp
+            .payload
+            .first()
+            .and_then(|policy| serde_json::from_str::<Policy>(policy).ok())
+        {
+            Some(policy) => {
+                println!(
+                    "auth_svc: loaded {} roles and {} permissions",
+                    policy.roles.len(),
+                    policy.permissions.len()
+                );
+                self.policy = policy;
+            }
+            None => eprintln!("auth_svc: ERROR could not load permission policy"),
+        }
+    }
+
+    /// Identity of whoever sent a packet, from the claims of its access token
+    /// Login has no token yet, so it is given the username it was sent for and no role
+    fn identify(&mut self, pkt: &RemotePacket) -> Identity {
+        match pkt.cmd_type {
+            1 => Identity {
+                username: pkt
+                    .payload
+                    .first()
+                    .and_then(|c| serde_json::from_str::<LoginCredentials>(c).ok())
+                    .map(|c| c.username.to_lowercase())
+                    .unwrap_or_default(),
+                ..Identity::default()
+            },
+            // refresh and logout may only carry a refresh token
+            2 | 4 if self.decode_token(&pkt.token, TokenKind::Access).is_none() => pkt
+                .payload
+                .first()
+                .and_then(|token| self.decode_token(token, TokenKind::Refresh))
+                .map(identity)
+                .unwrap_or_default(),
+            _ => self.check_token(&pkt.token),
+        }
+    }
+
+    /// Check for user token and return who it was issued to, recording activity on its session
+    /// Expired tokens, refresh tokens, tokens of revoked sessions
+    /// and tokens signed with a previous secret give an empty identity with ugroup 0
+    fn check_token(&mut self, token: &str) -> Identity {
+        let claims = match self.decode_token(token, TokenKind::Access) {
+            Some(claims) => claims,
+            None => return Identity::default(),
+        };
+
+        match self.sessions.get_mut(&claims.sid) {
+            Some(session) => {
+                session.last_activity = unix_time();
+                identity(claims)
+            }
+            None => Identity::default(),
+        }
+    }
+
+    /// Decode a token of the given kind whose session has not ended
+    fn decode_token(&mut self, token: &str, kind: TokenKind) -> Option<Claims> {
+        let mut validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS256);
+        validation.leeway = 0;
+
+        let claims = match jsonwebtoken::decode::<Claims>(
+            token,
+            &jsonwebtoken::DecodingKey::from_secret(&self.config.secret),
             &validation,
         ) {
-            Ok(c) => c.claims.ugroup,
-            Err(_) => 0,
+            Ok(c) if c.claims.kind == kind => c.claims,
+            _ => return None,
+        };
+
+        let now = unix_time();
+        self.sessions.retain(|_, session| session.expires > now);
+        if self.sessions.contains_key(&claims.sid) {
+            Some(claims)
+        } else {
+            None
         }
     }
 
-    /// Generate a token for the user with their usergroup
-    fn generate_token(&self, ugroup: u8) -> String {
+    /// Generate a token of the given kind for a session
+    fn generate_token(&self, session: &Session, kind: TokenKind) -> String {
+        let lifetime = match kind {
+            TokenKind::Access => self.config.access_lifetime,
+            TokenKind::Refresh => self.config.refresh_lifetime,
+        };
+        let iat = unix_time();
         let claims = Claims {
-            exp: 10000000000,
-            ugroup,
+            exp: iat + lifetime.as_secs(),
+            iat,
+            ugroup: session.ugroup,
+            username: session.username.clone(),
+            kind,
+            sid: session.id.clone(),
         };
 
         match jsonwebtoken::encode(
             &jsonwebtoken::Header::default(),
             &claims,
-            &jsonwebtoken::EncodingKey::from_secret(SECRET_KEY),
+            &jsonwebtoken::EncodingKey::from_secret(&self.config.secret),
         ) {
             Ok(token) => token,
             Err(_) => s!(""),
         }
     }
 
+    /// Answer a successful login or refresh with a new access token in the packet's token,
+    /// the usergroup, a new refresh token and the access token's lifetime in seconds
+    /// The session is extended until the new refresh token expires
+    fn issue_tokens(&mut self, cmd_type: u8, msg: &str, sid: &str) -> RemotePacket {
+        let session = match self.sessions.get_mut(sid) {
+            Some(session) => {
+                session.expires = unix_time() + self.config.refresh_lifetime.as_secs();
+                session.clone()
+            }
+            None => return RemotePacket::new(0, vec![s!("Session ended")]),
+        };
+
+        RemotePacket::new_with_auth(
+            cmd_type,
+            vec![
+                s!(msg),
+                s!(session.ugroup),
+                self.generate_token(&session, TokenKind::Refresh),
+                s!(self.config.access_lifetime.as_secs()),
+            ],
+            self.generate_token(&session, TokenKind::Access),
+        )
+    }
+
+    /// Start a session for a user who just logged in
+    fn start_session(&mut self, user: &User, addr: SocketAddr) -> String {
+        let id: String = (0..16)
+            .map(|_| format!("{:02x}", rand::thread_rng().gen::<u8>()))
+            .collect();
+        let now = unix_time();
+
+        self.sessions.insert(
+            id.clone(),
+            Session {
+                id: id.clone(),
+                username: user.name.clone(),
+                ugroup: user.ugroup,
+                client_addr: s!(addr),
+                created: now,
+                last_activity: now,
+                expires: now + self.config.refresh_lifetime.as_secs(),
+            },
+        );
+        println!("auth_svc: {} logged in from {}", user.name, addr);
+
+        id
+    }
+
+    /// Exchange the refresh token in payload[0] for a new access and refresh token
+    fn refresh(&mut self, pkt: &RemotePacket) -> RemotePacket {
+        let claims = pkt
+            .payload
+            .first()
+            .and_then(|token| self.decode_token(token, TokenKind::Refresh));
+
+        match claims {
+            Some(claims) => {
+                if let Some(session) = self.sessions.get_mut(&claims.sid) {
+                    session.last_activity = unix_time();
+                }
+                self.issue_tokens(2, "Refreshed", &claims.sid)
+            }
+            None => RemotePacket::new(0, vec![s!("Invalid or expired refresh token")]),
+        }
+    }
+
+    /// End the session of the access token sent with the packet,
+    /// or of the refresh token in payload[0] once the access token has expired
+    fn logout(&mut self, pkt: &RemotePacket) -> RemotePacket {
+        let claims = match self.decode_token(&pkt.token, TokenKind::Access) {
+            Some(claims) => Some(claims),
+            None => pkt
+                .payload
+                .first()
+                .and_then(|token| self.decode_token(token, TokenKind::Refresh)),
+        };
+
+        match claims.and_then(|claims| self.sessions.remove(&claims.sid)) {
+            Some(session) => {
+                println!("auth_svc: {} logged out", session.username);
+                RemotePacket::new(4, vec![s!("Logged out")])
+            }
+            None => RemotePacket::new(0, vec![s!("No active session")]),
+        }
+    }
+
+    /// Return every active session, most recently active first
+    fn list_sessions(&mut self) -> RemotePacket {
+        let now = unix_time();
+        self.sessions.retain(|_, session| session.expires > now);
+
+        let mut sessions: Vec<&Session> = self.sessions.values().collect();
+        sessions.sort_by_key(|s| Reverse(s.last_activity));
+
+        RemotePacket::new(5, vec![serde_json::to_string(&sessions).unwrap()])
+    }
+
+    /// End every session of the user named in payload[0]
+    fn revoke_sessions(&mut self, pkt: &RemotePacket) -> RemotePacket {
+        match pkt.payload.first() {
+            Some(name) => {
+                let revoked = self.revoke_user(name);
+                RemotePacket::new(6, vec![format!("{} session(s) revoked", revoked)])
+            }
+            None => RemotePacket::new(0, vec![s!("Malformed user information")]),
+        }
+    }
+
+    /// End every session of a user, return how many were ended
+    fn revoke_user(&mut self, name: &str) -> usize {
+        let name = name.to_lowercase();
+        let before = self.sessions.len();
+        self.sessions.retain(|_, session| session.username != name);
+
+        let revoked = before - self.sessions.len();
+        if revoked > 0 {
+            println!("auth_svc: revoked {} session(s) of {}", revoked, name);
+        }
+
+        revoked
+    }
+
+    /// Replace the signing secret with a newly generated one
+    /// Every token signed with the previous secret is rejected from then on
+    fn rotate_secret(&mut self) -> RemotePacket {
+        let path = match &self.config.secret_file {
+            Some(path) => path.clone(),
+            None => {
+                return RemotePacket::new(0, vec![s!("Signing secret is set by configuration")])
+            }
+        };
+
+        match create_secret(&path) {
+            Ok(secret) => {
+                self.config.secret = secret;
+                self.sessions.clear();
+                println!("auth_svc: signing secret rotated, previous tokens revoked");
+                RemotePacket::new(3, vec![s!("Signing secret rotated")])
+            }
+            Err(e) => {
+                eprintln!("auth_svc: {:#}", e);
+                RemotePacket::new(0, vec![s!("Signing secret rotation failed")])
+            }
+        }
+    }
+
+    /// Return every username and client address with failed logins, and whether each is locked out
+    fn list_lockouts(&mut self) -> RemotePacket {
+        self.throttle.purge(unix_time());
+
+        RemotePacket::new(7, vec![serde_json::to_string(&self.throttle).unwrap()])
+    }
+
+    /// Clear the failed logins and lockout of the username or client address in payload[0]
+    fn clear_lockout(&mut self, pkt: &RemotePacket) -> RemotePacket {
+        let key = match pkt.payload.first() {
+            Some(key) => key.to_lowercase(),
+            None => return RemotePacket::new(0, vec![s!("Malformed lockout information")]),
+        };
+
+        let cleared =
+            self.throttle.users.remove(&key).is_some() | self.throttle.addrs.remove(&key).is_some();
+        if cleared {
+            println!("auth_svc: lockout of {} cleared", key);
+            RemotePacket::new(8, vec![s!("Lockout cleared")])
+        } else {
+            RemotePacket::new(0, vec![s!("No failed logins recorded")])
+        }
+    }
+
     /// Query data_svc to check for matching user,
     /// authenticate with boringauth matching hashes of password
-    async fn login(&mut self, pkt: &RemotePacket) -> RemotePacket {
-        let credentials: LoginCredentials = serde_json::from_str(&pkt.payload[0].clone()).unwrap();
+    /// Refused without checking the password while the username or client address is locked out
+    async fn login(&mut self, pkt: &RemotePacket, addr: SocketAddr) -> RemotePacket {
+        let credentials = match pkt
+            .payload
+            .first()
+            .and_then(|c| serde_json::from_str::<LoginCredentials>(c).ok())
+        {
+            Some(credentials) => credentials,
+            None => return RemotePacket::new(0, vec![s!(LOGIN_FAILED)]),
+        };
+        let name = credentials.username.to_lowercase();
+        let ip = s!(addr.ip());
+        let now = unix_time();
+
+        self.throttle.purge(now);
+        if self.throttle.locked(&name, &ip, now) {
+            return RemotePacket::new(0, vec![s!("Too many failed logins, try again later")]);
+        }
+
         let user = User::new(credentials.username.clone(), s!("pwd"), 0);
         let user = serde_json::to_string(&user).unwrap();
 
-        if let Err(e) = self.tx_data.send(RemotePacket::new(161, vec![user])).await {
+        if let Err(e) = self
+            .tx_data
+            .send((RemotePacket::new(161, vec![user]), Identity::default()))
+            .await
+        {
             eprintln!("auth->database failed: {}", e);
         }
 
         let resp = self.rx_data.recv().await.unwrap();
 
-        match serde_json::from_str::<User>(&resp.payload[0]) {
-            Ok(user) => {
-                if is_valid(&credentials.password, &user.hash) {
-                    RemotePacket::new_with_auth(
-                        1,
-                        vec![s!("Authenticated"), s!(user.ugroup)],
-                        self.generate_token(user.ugroup),
-                    )
-                } else {
-                    if user.name == "" {
-                        RemotePacket::new(0, vec![s!("User not found")])
-                    } else {
-                        RemotePacket::new(0, vec![s!("Wrong password")])
-                    }
-                }
-            }
-            Err(_) => RemotePacket::new(0, vec![s!("Login Error")]),
+        let user = match serde_json::from_str::<User>(&resp.payload[0]) {
+            Ok(user) => user,
+            Err(_) => return RemotePacket::new(0, vec![s!("Login Error")]),
+        };
+
+        // an unknown user comes back with an empty name and hash,
+        // the password is still checked against a hash so the reply takes as long as for a known user
+        let known = !user.name.is_empty();
+        let hash = if known { &user.hash } else { dummy_hash() };
+
+        if is_valid(&credentials.password, hash) && known {
+            self.throttle.succeed(&name, &ip);
+            let sid = self.start_session(&user, addr);
+            self.issue_tokens(1, "Authenticated", &sid)
+        } else {
+            self.throttle.fail(&name, &ip, now);
+            RemotePacket::new(0, vec![s!(LOGIN_FAILED)])
         }
     }
 }
+
+/// Name of the user removed or changed by a database command, sent as a bare name for removal
+/// and inside a user object for group and password changes
+fn changed_user(pkt: &RemotePacket) -> Option<String> {
+    let payload = pkt.payload.first()?;
+    match pkt.cmd_type {
+        163 => Some(payload.clone()),
+        _ => serde_json::from_str::<serde_json::Value>(payload)
+            .ok()?
+            .get("name")?
+            .as_str()
+            .map(|name| s!(name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locks_out_after_limit_failures_within_window() {
+        let mut attempts = LoginAttempts::default();
+        let now = 1_000_000;
+
+        assert!(!attempts.fail(3, now));
+        assert!(!attempts.fail(3, now + 10));
+        assert!(attempts.fail(3, now + 20));
+        assert!(attempts.locked(now + 20));
+        assert!(attempts.locked(now + 20 + LOCKOUT_DURATION - 1));
+        assert!(!attempts.locked(now + 20 + LOCKOUT_DURATION));
+    }
+
+    #[test]
+    fn failures_outside_window_start_a_new_count() {
+        let mut attempts = LoginAttempts::default();
+        let now = 1_000_000;
+
+        assert!(!attempts.fail(3, now));
+        assert!(!attempts.fail(3, now + 10));
+        assert!(!attempts.fail(3, now + FAILURE_WINDOW + 1));
+        assert_eq!(attempts.failures, 1);
+        assert!(!attempts.locked(now + FAILURE_WINDOW + 1));
+    }
+
+    #[test]
+    fn failures_during_lockout_do_not_extend_it() {
+        let mut attempts = LoginAttempts::default();
+        let now = 1_000_000;
+
+        attempts.fail(1, now);
+        let locked_until = attempts.locked_until;
+
+        assert!(!attempts.fail(1, now + 60));
+        assert_eq!(attempts.locked_until, locked_until);
+    }
+
+    #[test]
+    fn stale_once_window_passes_without_lockout() {
+        let mut attempts = LoginAttempts::default();
+        let now = 1_000_000;
+
+        attempts.fail(3, now);
+        assert!(!attempts.stale(now + FAILURE_WINDOW));
+        assert!(attempts.stale(now + FAILURE_WINDOW + 1));
+
+        attempts.fail(1, now + FAILURE_WINDOW + 1);
+        assert!(!attempts.stale(now + 2 * FAILURE_WINDOW + 2));
+    }
+}
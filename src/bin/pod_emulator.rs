@@ -0,0 +1,505 @@
+//! Emulates one or more embedded devices speaking the PodPacket protocol over TCP,
+//! so PodConnSvc can be exercised without real boards.
+//!
+//! Usage: pod_emulator [config file]   (defaults to emulator.json)
+//!
+//! Unsolicited messages can be sent to the pod by typing lines on stdin:
+//!     <device id> fault|warning|log <code> <message>
+//! e.g. `battery fault 3 e-stop pressed`
+
+use anyhow::{anyhow, Context, Result};
+use rand::Rng;
+use serde::Deserialize;
+use std::{net::SocketAddr, sync::Arc};
+use tokio::{
+    io::{stdin, AsyncBufReadExt, AsyncRead, AsyncWrite, BufReader},
+    net::{TcpListener, UdpSocket},
+    select, spawn,
+    sync::broadcast,
+    time::{interval, Duration, Interval, MissedTickBehavior},
+};
+use tokio_rustls::{
+    rustls::{self, ServerConfig},
+    TlsAcceptor,
+};
+
+use openlink_pod::pod_frame::{FramedConn, PodFrameCodec, DEFAULT_MAX_FRAME_SIZE};
+use openlink_pod::pod_packet::{
+    encode, PodPacket, PodPacketError, CMD_ANNOUNCE, CMD_CORRUPTED, CMD_FAULT, CMD_HANDSHAKE,
+    CMD_HEARTBEAT, CMD_LOG, CMD_SUBSCRIBE, CMD_TELEMETRY, CMD_WARNING, PROTOCOL_VERSION,
+};
+use openlink_pod::pod_packet_payload::{
+    decode_payload, encode_payload, CommandArg, FieldType, FieldValue, PodPacketPayload,
+};
+use openlink_pod::s;
+
+/// Config file used when none is given on the command line
+const DEFAULT_CONFIG: &str = "emulator.json";
+
+/// Error codes returned in target_cmd_code when a command is rejected with cmd 0
+const ERR_UNKNOWN_CMD: u8 = 1;
+const ERR_BAD_ARGS: u8 = 2;
+
+/// Every fake device described by the config file
+#[derive(Deserialize)]
+struct EmulatorConfig {
+    devices: Vec<EmulatedDevice>,
+    //where every device announces itself once a second,
+    //e.g. 255.255.255.255:45454 for broadcast or 127.0.0.1:45454 on localhost
+    announce: Option<SocketAddr>,
+}
+
+/// Single fake device, listening on its own address
+#[derive(Deserialize)]
+struct EmulatedDevice {
+    id: String,
+    listen: SocketAddr,
+    #[serde(default = "default_firmware")]
+    firmware: String,
+    //protocol version reported in the handshake and every reply
+    #[serde(default = "default_version")]
+    version: u8,
+    #[serde(default)]
+    fields: Vec<EmulatedField>,
+    #[serde(default)]
+    commands: Vec<EmulatedCommand>,
+    //PEM files, serve the device over TLS when both are given
+    tls_cert: Option<String>,
+    tls_key: Option<String>,
+}
+
+/// Telemetry field reported at discovery, with the range its readings wander within
+#[derive(Deserialize)]
+struct EmulatedField {
+    name: String,
+    field_type: FieldType,
+    #[serde(default)]
+    min: f64,
+    #[serde(default = "default_max")]
+    max: f64,
+}
+
+/// Device specific command reported at discovery, acknowledged when received
+#[derive(Deserialize)]
+struct EmulatedCommand {
+    name: String,
+    code: u8,
+    #[serde(default)]
+    args: Vec<CommandArg>,
+}
+
+/// Unsolicited message typed on stdin, sent by the named device on every open connection
+#[derive(Clone)]
+struct Injected {
+    device_id: String,
+    cmd: u8,
+    code: u8,
+    message: String,
+}
+
+fn default_firmware() -> String {
+    s!("emulator")
+}
+
+fn default_version() -> u8 {
+    PROTOCOL_VERSION
+}
+
+fn default_max() -> f64 {
+    100.0
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| s!(DEFAULT_CONFIG));
+    let config = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read emulator config {}", path))?;
+    let config: EmulatorConfig = serde_json::from_str(&config)
+        .with_context(|| format!("failed to parse emulator config {}", path))?;
+
+    let (tx_injected, _) = broadcast::channel::<Injected>(16);
+    let mut listeners = Vec::new();
+    let mut announcements = Vec::new();
+    for device in config.devices {
+        let tls =
+            match (&device.tls_cert, &device.tls_key) {
+                (Some(cert), Some(key)) => Some(tls_acceptor(cert, key).with_context(|| {
+                    format!("failed to load TLS files for device {}", device.id)
+                })?),
+                _ => None,
+            };
+
+        let listener = TcpListener::bind(device.listen).await.with_context(|| {
+            format!("failed to bind {} for device {}", device.listen, device.id)
+        })?;
+        println!(
+            "pod_emulator: device {} listening on {} ({} fields, {} commands, {})",
+            device.id,
+            device.listen,
+            device.fields.len(),
+            device.commands.len(),
+            if tls.is_some() { "tls" } else { "plain" }
+        );
+        let device = Arc::new(device);
+        announcements.push(device.clone());
+        listeners.push(spawn(serve(listener, tls, device, tx_injected.clone())));
+    }
+
+    spawn(read_stdin(tx_injected));
+
+    if let Some(addr) = config.announce {
+        println!("pod_emulator: announcing devices to {}", addr);
+        listeners.push(spawn(announce(addr, announcements)));
+    }
+
+    for listener in listeners {
+        listener.await?;
+    }
+
+    Ok(())
+}
+
+/// Load a device certificate chain and private key for serving TLS
+fn tls_acceptor(cert_path: &str, key_path: &str) -> Result<TlsAcceptor> {
+    let certs = rustls_pemfile::certs(&mut std::fs::read(cert_path)?.as_slice())?;
+    let certs = certs.into_iter().map(rustls::Certificate).collect();
+
+    let key = std::fs::read(key_path)?;
+    let mut reader = key.as_slice();
+    let key = loop {
+        match rustls_pemfile::read_one(&mut reader)? {
+            Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => break rustls::PrivateKey(key),
+            Some(_) => {}
+            None => return Err(anyhow!("no private key found in {}", key_path)),
+        }
+    };
+
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Announce every device once a second, as a device waiting to be adopted by the pod would
+async fn announce(addr: SocketAddr, devices: Vec<Arc<EmulatedDevice>>) {
+    let socket = match UdpSocket::bind("0.0.0.0:0").await {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("pod_emulator: announcement socket failed: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = socket.set_broadcast(true) {
+        eprintln!("pod_emulator: could not enable broadcast: {}", e);
+    }
+
+    let codec = PodFrameCodec::new(DEFAULT_MAX_FRAME_SIZE);
+    let mut timer = interval(Duration::from_secs(1));
+    loop {
+        timer.tick().await;
+        for device in devices.iter() {
+            let mut payload = PodPacketPayload::new();
+            payload.target_id = device.id.clone();
+            payload.firmware = device.firmware.clone();
+            payload.port = device.listen.port();
+
+            let mut pkt = PodPacket::new(CMD_ANNOUNCE, encode_payload(payload));
+            pkt.version = device.version;
+
+            let res = match codec.encode_frame(&encode(pkt)) {
+                Ok(frame) => socket.send_to(&frame, addr).await.map_err(|e| s!(e)),
+                Err(e) => Err(s!(e)),
+            };
+            if let Err(e) = res {
+                eprintln!(
+                    "pod_emulator: device {} announcement failed: {}",
+                    device.id, e
+                );
+            }
+        }
+    }
+}
+
+/// Parse unsolicited messages typed on stdin and hand them to every connection
+async fn read_stdin(tx_injected: broadcast::Sender<Injected>) {
+    let mut lines = BufReader::new(stdin()).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let mut parts = line.trim().splitn(4, ' ');
+        let (device_id, severity, code) = (parts.next(), parts.next(), parts.next());
+
+        let cmd = match severity {
+            Some("fault") => CMD_FAULT,
+            Some("warning") => CMD_WARNING,
+            Some("log") => CMD_LOG,
+            _ => {
+                println!("pod_emulator: expected <device id> fault|warning|log <code> <message>");
+                continue;
+            }
+        };
+        let code = match code.map(|code| code.parse::<u8>()) {
+            Some(Ok(code)) => code,
+            _ => {
+                println!("pod_emulator: message code must be 0-255");
+                continue;
+            }
+        };
+
+        let msg = Injected {
+            device_id: s!(device_id.unwrap_or_default()),
+            cmd,
+            code,
+            message: s!(parts.next().unwrap_or_default()),
+        };
+        if tx_injected.send(msg).is_err() {
+            println!("pod_emulator: no device is connected to the pod");
+        }
+    }
+}
+
+/// Accept connections from the pod for a single device, one task per connection
+async fn serve(
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    device: Arc<EmulatedDevice>,
+    tx_injected: broadcast::Sender<Injected>,
+) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                println!("pod_emulator: device {} accepted {}", device.id, addr);
+                let device = device.clone();
+                let tls = tls.clone();
+                let injected = tx_injected.subscribe();
+                spawn(async move {
+                    let res = match tls {
+                        Some(tls) => match tls.accept(stream).await {
+                            Ok(stream) => handle_conn(stream, &device, injected).await,
+                            Err(e) => Err(e.into()),
+                        },
+                        None => handle_conn(stream, &device, injected).await,
+                    };
+                    if let Err(e) = res {
+                        println!("pod_emulator: device {} connection ended: {}", device.id, e);
+                    }
+                });
+            }
+            Err(e) => eprintln!("pod_emulator: device {} accept failed: {}", device.id, e),
+        }
+    }
+}
+
+/// Answer commands from the pod until it disconnects, and send it any message typed for this device
+/// Readings start in the middle of each field's range and drift with every telemetry request
+/// or pushed reading
+async fn handle_conn<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    device: &EmulatedDevice,
+    mut injected: broadcast::Receiver<Injected>,
+) -> Result<()> {
+    let mut conn = FramedConn::new(stream, DEFAULT_MAX_FRAME_SIZE);
+    let mut readings: Vec<f64> = device
+        .fields
+        .iter()
+        .map(|field| (field.min + field.max) / 2.0)
+        .collect();
+    //timer and field indices of the telemetry subscription, if any
+    let mut push: Option<(Interval, Vec<usize>)> = None;
+
+    loop {
+        let pkt = select! {
+            pkt = conn.recv() => match pkt {
+                Ok(pkt) => pkt,
+                //report a frame that failed its checksum, then drop the link
+                //since the stream may no longer be aligned
+                Err(e) if matches!(e.downcast_ref(), Some(PodPacketError::Corrupted)) => {
+                    let mut pkt = PodPacket::new(CMD_CORRUPTED, encode_payload(PodPacketPayload::new()));
+                    pkt.version = device.version;
+                    conn.send(pkt).await?;
+                    return Err(e);
+                }
+                Err(e) => return Err(e),
+            },
+            Ok(msg) = injected.recv() => {
+                if msg.device_id == device.id {
+                    let mut payload = PodPacketPayload::new();
+                    payload.target_id = device.id.clone();
+                    payload.target_cmd_code = msg.code;
+                    payload.message = msg.message;
+
+                    //unsolicited messages are sent with seq 0
+                    let mut pkt = PodPacket::new(msg.cmd, encode_payload(payload));
+                    pkt.version = device.version;
+                    conn.send(pkt).await?;
+                }
+                continue;
+            }
+            _ = async { push.as_mut().unwrap().0.tick().await }, if push.is_some() => {
+                let mut payload = PodPacketPayload::new();
+                payload.target_id = device.id.clone();
+                drift(device, &mut readings);
+                for &index in push.as_ref().unwrap().1.iter() {
+                    let field = &device.fields[index];
+                    payload.field_names.push(field.name.clone());
+                    payload.telemetry_data.push(to_value(field.field_type, readings[index]));
+                }
+
+                //pushed readings are sent with seq 0
+                let mut pkt = PodPacket::new(CMD_TELEMETRY, encode_payload(payload));
+                pkt.version = device.version;
+                conn.send(pkt).await?;
+                continue;
+            }
+        };
+        let req = decode_payload(pkt.payload)?;
+        let mut payload = PodPacketPayload::new();
+        payload.target_id = device.id.clone();
+
+        let cmd_type = match pkt.cmd_type {
+            CMD_HANDSHAKE => {
+                payload.firmware = device.firmware.clone();
+                CMD_HANDSHAKE
+            }
+            1 => {
+                for field in device.fields.iter() {
+                    payload.field_names.push(field.name.clone());
+                    payload.field_types.push(field.field_type);
+                }
+                for cmd in device.commands.iter() {
+                    payload.command_names.push(cmd.name.clone());
+                    payload.command_codes.push(cmd.code);
+                    payload.command_args.push(cmd.args.clone());
+                }
+                1
+            }
+            //disconnect, no reply
+            2 => {
+                println!("pod_emulator: device {} disconnected by pod", device.id);
+                return Ok(());
+            }
+            CMD_TELEMETRY => {
+                drift(device, &mut readings);
+                for name in req.field_names.iter() {
+                    if let Some(index) = device.fields.iter().position(|f| &f.name == name) {
+                        let field = &device.fields[index];
+                        payload.field_names.push(field.name.clone());
+                        payload
+                            .telemetry_data
+                            .push(to_value(field.field_type, readings[index]));
+                    }
+                }
+                CMD_TELEMETRY
+            }
+            CMD_HEARTBEAT => CMD_HEARTBEAT,
+            CMD_SUBSCRIBE => {
+                let indices: Option<Vec<usize>> = req
+                    .field_names
+                    .iter()
+                    .map(|name| device.fields.iter().position(|f| &f.name == name))
+                    .collect();
+                match indices {
+                    Some(_) if req.rate_hz == 0 => {
+                        println!("pod_emulator: device {} unsubscribed", device.id);
+                        push = None;
+                        CMD_SUBSCRIBE
+                    }
+                    Some(indices) => {
+                        println!(
+                            "pod_emulator: device {} pushing {:?} at {}Hz",
+                            device.id, req.field_names, req.rate_hz
+                        );
+                        let mut timer = interval(Duration::from_secs_f64(1.0 / req.rate_hz as f64));
+                        timer.set_missed_tick_behavior(MissedTickBehavior::Skip);
+                        push = Some((timer, indices));
+                        CMD_SUBSCRIBE
+                    }
+                    None => {
+                        println!(
+                            "pod_emulator: device {} cannot push unknown fields {:?}",
+                            device.id, req.field_names
+                        );
+                        payload.target_cmd_code = ERR_BAD_ARGS;
+                        0
+                    }
+                }
+            }
+            255 => {
+                println!("pod_emulator: device {} braking", device.id);
+                255
+            }
+            254 => {
+                println!("pod_emulator: device {} launching", device.id);
+                254
+            }
+            //device specific command, echo its arguments back as returned values
+            cmd => match device.commands.iter().find(|c| c.code == cmd) {
+                Some(command) if command.args.len() == req.args.len() => {
+                    println!(
+                        "pod_emulator: device {} received command {} {:?}",
+                        device.id, command.name, req.args
+                    );
+                    for (arg, value) in command.args.iter().zip(req.args) {
+                        payload.field_names.push(arg.name.clone());
+                        payload.telemetry_data.push(value);
+                    }
+                    cmd
+                }
+                Some(command) => {
+                    println!(
+                        "pod_emulator: device {} command {} expects {} argument(s), received {}",
+                        device.id,
+                        command.name,
+                        command.args.len(),
+                        req.args.len()
+                    );
+                    payload.target_cmd_code = ERR_BAD_ARGS;
+                    0
+                }
+                None => {
+                    println!("pod_emulator: device {} unknown command {}", device.id, cmd);
+                    payload.target_cmd_code = ERR_UNKNOWN_CMD;
+                    0
+                }
+            },
+        };
+
+        let mut resp = PodPacket::new(cmd_type, encode_payload(payload));
+        resp.version = device.version;
+        resp.seq = pkt.seq;
+        conn.send(resp).await?;
+    }
+}
+
+/// Move every reading a small random step, staying within its field's range
+fn drift(device: &EmulatedDevice, readings: &mut [f64]) {
+    let mut rng = rand::thread_rng();
+    for (field, reading) in device.fields.iter().zip(readings.iter_mut()) {
+        let step = (field.max - field.min) * 0.05;
+        if step > 0.0 {
+            *reading = (*reading + rng.gen_range(-step..step)).clamp(field.min, field.max);
+        }
+    }
+}
+
+/// Convert a reading to the type its field was declared with
+fn to_value(field_type: FieldType, reading: f64) -> FieldValue {
+    match field_type {
+        FieldType::U8 => FieldValue::U8(reading as u8),
+        FieldType::U16 => FieldValue::U16(reading as u16),
+        FieldType::U32 => FieldValue::U32(reading as u32),
+        FieldType::U64 => FieldValue::U64(reading as u64),
+        FieldType::I8 => FieldValue::I8(reading as i8),
+        FieldType::I16 => FieldValue::I16(reading as i16),
+        FieldType::I32 => FieldValue::I32(reading as i32),
+        FieldType::I64 => FieldValue::I64(reading as i64),
+        FieldType::F32 => FieldValue::F32(reading as f32),
+        FieldType::F64 => FieldValue::F64(reading),
+        FieldType::Bool => FieldValue::Bool(reading >= 0.5),
+        FieldType::Str => FieldValue::Str(format!("{:.2}", reading)),
+    }
+}
@@ -0,0 +1,14 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Current unix time in seconds, 0 if the system clock is set before 1970
+pub fn unix_time() -> u64 {
+    unix_time_ms() / 1000
+}
+
+/// Current unix time in milliseconds, 0 if the system clock is set before 1970
+pub fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|t| t.as_millis() as u64)
+        .unwrap_or(0)
+}
@@ -14,7 +14,8 @@ Payload should be a bool to engage/disengage
 */
 
 use crate::pod_packet::PodPacket;
-use crate::pod_packet_payload::{encode_payload, PodPacketPayload};
+use crate::pod_packet_payload::{decode_payload, encode_payload, PodPacketPayload};
+use crate::user::Identity;
 use shared::{launch::LaunchParams, remote_conn_packet::RemotePacket};
 
 use super::pod_conn_svc::PodState;
@@ -28,13 +29,14 @@ pub struct CtrlSvc {
     pub pod_state: Arc<Mutex<PodState>>,
 
     //connections to other services
-    pub rx_auth: Receiver<RemotePacket>,
+    pub rx_auth: Receiver<(RemotePacket, Identity)>,
     pub tx_auth: Sender<RemotePacket>,
 
     pub rx_pod: Receiver<PodPacket>,
     pub tx_pod: Sender<PodPacket>,
 
-    pub tx_trip: Sender<LaunchParams>,
+    // each trip goes with the operator who launched it
+    pub tx_trip: Sender<(LaunchParams, Identity)>,
 }
 
 impl CtrlSvc {
@@ -42,15 +44,15 @@ impl CtrlSvc {
     pub async fn run(mut self) -> Result<()> {
         println!("ctrl_svc: service running");
 
-        while let Some(pkt) = self.rx_auth.recv().await {
-            println!("Command type: {}", pkt.cmd_type);
+        while let Some((pkt, identity)) = self.rx_auth.recv().await {
+            println!("Command type: {} from {}", pkt.cmd_type, identity.username);
 
             let resp = match pkt.cmd_type {
                 //64 is the beginning of the command space for ctrl_svc
                 64 => self.get_state().await.unwrap(),
                 68 => self.set_destination(pkt.payload[0].clone()).await.unwrap(),
-                69 => self.launch_pod().await.unwrap(),
-                99 => self.engage_brakes().await.unwrap(),
+                69 => self.launch_pod(&identity).await.unwrap(),
+                99 => self.engage_brakes(&identity).await.unwrap(),
                 _ => RemotePacket::new(0, vec![s!("Invalid command")]),
                 //127 is the end of the command space for ctrl_svc
             };
@@ -76,7 +78,7 @@ impl CtrlSvc {
     }
 
     /// Launch the pod if in valid state
-    async fn launch_pod(&mut self) -> Result<RemotePacket, ()> {
+    async fn launch_pod(&mut self, operator: &Identity) -> Result<RemotePacket, ()> {
         let launch = match *self.pod_state.lock().await {
             PodState::Locked => true,
             _ => false,
@@ -92,19 +94,43 @@ impl CtrlSvc {
                 eprintln!("ctrl->pod failed: {}", e);
             }
 
-            //receive the ACK from pod_conn_svc
-            self.rx_pod.recv().await;
+            //receive the ACK from pod_conn_svc, cmd_type 0 means at least one device did not acknowledge
+            //pod_conn_svc has then braked every device, and left the pod Moving if the brakes were not acknowledged
+            //a launch refused before any device was commanded carries the reason in its message
+            let ack = self.rx_pod.recv().await;
+            let refusal = ack
+                .as_ref()
+                .and_then(|pkt| decode_payload(pkt.payload.clone()).ok())
+                .map(|payload| payload.message)
+                .filter(|message| !message.is_empty());
+            if !acknowledged(ack) {
+                let msg = match (refusal, &*self.pod_state.lock().await) {
+                    (Some(refusal), _) => refusal,
+                    (None, PodState::Moving) => {
+                        s!("Launch not acknowledged by every device and brakes not acknowledged, pod is Moving")
+                    }
+                    (None, _) => {
+                        s!("Launch not acknowledged by every device, brakes engaged and pod stays Locked")
+                    }
+                };
+                eprintln!("ctrl: {}", msg);
+                return Ok(RemotePacket::new(0, vec![msg]));
+            }
 
             println!("ctrl: received ACK from pod_conn");
 
             // Once OK() is received, change state to PodState::Moving
             *self.pod_state.lock().await = PodState::Moving;
 
-            if let Err(e) = self.tx_trip.send(self.launch_params.clone()).await {
+            if let Err(e) = self
+                .tx_trip
+                .send((self.launch_params.clone(), operator.clone()))
+                .await
+            {
                 eprintln!("ctrl->trip failed: {}", e);
             }
 
-            println!("Pod launched");
+            println!("Pod launched by {}", operator.username);
             // return the appropriate ACK packet wrapped in OK()
             return Ok(RemotePacket::new(69, vec![s!("Pod launched")]));
         } else {
@@ -116,7 +142,7 @@ impl CtrlSvc {
     }
 
     /// Engage brakes if in valid state
-    async fn engage_brakes(&mut self) -> Result<RemotePacket, ()> {
+    async fn engage_brakes(&mut self, operator: &Identity) -> Result<RemotePacket, ()> {
         let moving = match *self.pod_state.lock().await {
             PodState::Moving => true,
             _ => false,
@@ -131,10 +157,17 @@ impl CtrlSvc {
                 eprintln!("ctrl->pod failed: {}", e);
             }
 
-            self.rx_pod.recv().await;
+            // the pod stays Moving on failure so the brake command can be sent again
+            if !acknowledged(self.rx_pod.recv().await) {
+                eprintln!("ctrl: brakes not acknowledged by every device");
+                return Ok(RemotePacket::new(
+                    0,
+                    vec![s!("Brakes not acknowledged by every device")],
+                ));
+            }
 
             *self.pod_state.lock().await = PodState::Braking;
-            println!("Pod braking");
+            println!("Pod braking, brakes engaged by {}", operator.username);
 
             return Ok(RemotePacket::new(96, vec![s!("Pod brakes engaged")]));
         } else {
@@ -185,3 +218,8 @@ impl CtrlSvc {
         }
     }
 }
+
+/// Check the single aggregated ACK pod_conn_svc returns after a broadcast command
+fn acknowledged(ack: Option<PodPacket>) -> bool {
+    matches!(ack, Some(pkt) if pkt.cmd_type != 0)
+}
@@ -0,0 +1,185 @@
+use rusqlite::{params, Connection};
+
+use super::super::{audit::*, RemotePacket};
+
+/// Handler for audit log related database cmd_types
+pub fn handler(conn: &Connection, mut pkt: RemotePacket) -> RemotePacket {
+    match pkt.cmd_type {
+        171 => {
+            // an empty payload queries the whole log
+            let query = match pkt.payload.first() {
+                Some(q) => serde_json::from_str::<AuditQuery>(q).ok(),
+                None => Some(AuditQuery::default()),
+            };
+
+            match query {
+                Some(query) => match get_entries(conn, &query) {
+                    Some(entries) => pkt.payload = vec![serde_json::to_string(&entries).unwrap()],
+                    None => pkt = pkt.error(s!("Audit log query failed")),
+                },
+                None => pkt = pkt.error(s!("Malformed audit query")),
+            }
+
+            pkt
+        }
+        _ => pkt,
+    }
+}
+
+/// Store a record of a packet handled by auth_svc
+pub fn add_entry(conn: &Connection, entry: &AuditEntry) -> bool {
+    conn.execute(
+        "INSERT INTO audit_log (time, username, role, client_addr, cmd_type, summary, outcome)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            entry.time,
+            entry.username,
+            entry.role,
+            entry.client_addr,
+            entry.cmd_type,
+            entry.summary,
+            entry.outcome
+        ],
+    )
+    .is_ok()
+}
+
+/// Delete entries older than the given unix time in seconds
+pub fn prune(conn: &Connection, before: u64) -> bool {
+    conn.execute("DELETE FROM audit_log WHERE time < ?1", params![before])
+        .is_ok()
+}
+
+/// Get the newest entries matching a query, at most MAX_AUDIT_RESULTS
+/// cmd_type = 171
+pub fn get_entries(conn: &Connection, query: &AuditQuery) -> Option<Vec<AuditEntry>> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT time, username, role, client_addr, cmd_type, summary, outcome FROM audit_log
+                WHERE (?1 IS NULL OR time >= ?1)
+                AND (?2 IS NULL OR time <= ?2)
+                AND (?3 IS NULL OR username = ?3)
+                AND (?4 IS NULL OR cmd_type = ?4)
+                ORDER BY id DESC LIMIT ?5",
+        )
+        .ok()?;
+    let mut rows = stmt
+        .query(params![
+            query.from,
+            query.to,
+            query.username.as_ref().map(|name| name.to_lowercase()),
+            query.cmd_type,
+            MAX_AUDIT_RESULTS
+        ])
+        .ok()?;
+
+    let mut entries = Vec::new();
+    while let Some(row) = rows.next().ok()? {
+        entries.push(AuditEntry {
+            time: row.get(0).ok()?,
+            username: row.get(1).ok()?,
+            role: row.get(2).ok()?,
+            client_addr: row.get(3).ok()?,
+            cmd_type: row.get(4).ok()?,
+            summary: row.get(5).ok()?,
+            outcome: row.get(6).ok()?,
+        })
+    }
+
+    Some(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        super::super::schema::create(&conn).unwrap();
+        conn
+    }
+
+    fn entry(time: u64, username: &str, cmd_type: u8) -> AuditEntry {
+        AuditEntry {
+            time,
+            username: s!(username),
+            role: 1,
+            client_addr: s!("127.0.0.1:4000"),
+            cmd_type,
+            summary: s!(""),
+            outcome: s!("OK"),
+        }
+    }
+
+    fn query(conn: &Connection, payload: Vec<String>) -> RemotePacket {
+        handler(conn, RemotePacket::new(171, payload))
+    }
+
+    fn entries(pkt: &RemotePacket) -> Vec<AuditEntry> {
+        serde_json::from_str(&pkt.payload[0]).unwrap()
+    }
+
+    #[test]
+    fn queries_filter_entries_newest_first() {
+        let conn = open();
+        assert!(add_entry(&conn, &entry(100, "alice", 69)));
+        assert!(add_entry(&conn, &entry(200, "bob", 128)));
+        assert!(add_entry(&conn, &entry(300, "alice", 128)));
+
+        let all = entries(&query(&conn, vec![]));
+        let times: Vec<u64> = all.iter().map(|e| e.time).collect();
+        assert_eq!(times, vec![300, 200, 100]);
+
+        let q = AuditQuery {
+            from: Some(150),
+            username: Some(s!("Alice")),
+            ..Default::default()
+        };
+        let found = entries(&query(&conn, vec![serde_json::to_string(&q).unwrap()]));
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].time, 300);
+
+        let q = AuditQuery {
+            cmd_type: Some(128),
+            to: Some(250),
+            ..Default::default()
+        };
+        let found = entries(&query(&conn, vec![serde_json::to_string(&q).unwrap()]));
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].username, "bob");
+    }
+
+    #[test]
+    fn rejects_malformed_queries() {
+        let conn = open();
+        assert_eq!(query(&conn, vec![s!("not json")]).cmd_type, 0);
+    }
+
+    #[test]
+    fn prunes_entries_before_the_cutoff() {
+        let conn = open();
+        for time in [100, 200, 300] {
+            assert!(add_entry(&conn, &entry(time, "alice", 69)));
+        }
+
+        assert!(prune(&conn, 200));
+        let times: Vec<u64> = get_entries(&conn, &AuditQuery::default())
+            .unwrap()
+            .iter()
+            .map(|e| e.time)
+            .collect();
+        assert_eq!(times, vec![300, 200]);
+    }
+
+    #[test]
+    fn caps_query_results() {
+        let conn = open();
+        for time in 0..MAX_AUDIT_RESULTS as u64 + 5 {
+            assert!(add_entry(&conn, &entry(time, "alice", 69)));
+        }
+
+        let found = get_entries(&conn, &AuditQuery::default()).unwrap();
+        assert_eq!(found.len(), MAX_AUDIT_RESULTS as usize);
+        assert_eq!(found[0].time, MAX_AUDIT_RESULTS as u64 + 4);
+    }
+}
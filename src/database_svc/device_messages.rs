@@ -0,0 +1,19 @@
+use rusqlite::{params, Connection};
+
+use crate::pod_conn_svc::DeviceMessage;
+
+/// Store a fault, warning or log message sent by a device, passed on by tele_svc
+pub fn add_message(conn: &Connection, msg: &DeviceMessage) -> bool {
+    conn.execute(
+        "INSERT INTO device_messages (time, device_id, severity, code, message)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            msg.time,
+            msg.device_id,
+            format!("{:?}", msg.severity),
+            msg.code,
+            msg.message
+        ],
+    )
+    .is_ok()
+}
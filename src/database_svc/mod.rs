@@ -1,22 +1,39 @@
 use rusqlite::{Connection, Result};
-use tokio::sync::{mpsc::Receiver, mpsc::Sender};
+use tokio::{
+    sync::{mpsc::Receiver, mpsc::Sender},
+    time::{interval, Duration},
+};
 
 use super::RemotePacket;
+use crate::audit::{AuditEntry, AUDIT_RETENTION};
+use crate::clock::unix_time;
+use crate::pod_conn_svc::DeviceMessage;
+use crate::trip_svc::TripRecord;
+use crate::user::Identity;
 
+pub mod audit;
+pub mod device_messages;
 //pub mod devices;
+pub mod roles;
 mod schema;
 //pub mod telemetry;
+pub mod trips;
 pub mod users;
 
-const DB_VER: f32 = 0.1;
+const DB_VER: f32 = 0.5;
 const ADMIN_PASS: &str = "password";
 
+/// How often audit log entries older than AUDIT_RETENTION are deleted
+const AUDIT_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
 pub struct DatabaseSvc {
-    pub rx_auth: Receiver<RemotePacket>,
+    pub rx_auth: Receiver<(RemotePacket, Identity)>,
     pub tx_auth: Sender<RemotePacket>,
+    pub rx_audit: Receiver<AuditEntry>,
     //pub rx_link: Receiver<>,
     //pub tx_link: Sender<>,
-    //pub rx_tele: Receiver<>,
+    pub rx_tele: Receiver<DeviceMessage>,
+    pub rx_trip: Receiver<TripRecord>,
     //pub tx_tele: Receiver<>,
 }
 
@@ -42,21 +59,48 @@ impl DatabaseSvc {
             Err(_) => schema::create(&conn)?,
         }
 
+        // the first tick is immediate, so old entries are pruned on startup
+        let mut prune_audit = interval(AUDIT_PRUNE_INTERVAL);
+
         loop {
             tokio::select! {
-                Some(pkt) = self.rx_auth.recv() => {
-                    let res = users::handler(&conn, pkt);
+                Some((pkt, identity)) = self.rx_auth.recv() => {
+                    let res = match pkt.cmd_type {
+                        166..=170 => roles::handler(&conn, pkt),
+                        171 => audit::handler(&conn, pkt),
+                        _ => users::handler(&conn, pkt, &identity),
+                    };
 
                     if let Err(e) = self.tx_auth.send(res).await {
                         eprintln!("data->auth failed: {}", e);
                     }
                 }
 
-                /*_ = self.rx_link.recv() => {
+                Some(msg) = self.rx_tele.recv() => {
+                    if !device_messages::add_message(&conn, &msg) {
+                        eprintln!("database_svc: ERROR could not store message from device {}", msg.device_id);
+                    }
+                }
 
+                Some(entry) = self.rx_audit.recv() => {
+                    if !audit::add_entry(&conn, &entry) {
+                        eprintln!("database_svc: ERROR could not store audit entry for cmd {}", entry.cmd_type);
+                    }
                 }
 
-                _ = self.rx_tele.recv() => {
+                Some(trip) = self.rx_trip.recv() => {
+                    if !trips::add_trip(&conn, &trip) {
+                        eprintln!("database_svc: ERROR could not store trip launched by {}", trip.username);
+                    }
+                }
+
+                _ = prune_audit.tick() => {
+                    if !audit::prune(&conn, unix_time().saturating_sub(AUDIT_RETENTION)) {
+                        eprintln!("database_svc: ERROR could not prune audit log");
+                    }
+                }
+
+                /*_ = self.rx_link.recv() => {
 
                 }*/
             }
@@ -0,0 +1,200 @@
+use rusqlite::{params, Connection};
+
+use super::super::{role::*, RemotePacket};
+
+/// Lowest id given to a role created at runtime
+const FIRST_CUSTOM_ROLE: u8 = 4;
+
+/// Handler for all role and policy related database cmd_types
+pub fn handler(conn: &Connection, mut pkt: RemotePacket) -> RemotePacket {
+    match pkt.cmd_type {
+        166 => {
+            pkt.payload = vec![serde_json::to_string(&get_policy(conn)).unwrap()];
+            pkt
+        }
+        167 => {
+            match pkt.payload.first().map(|name| name.trim().to_lowercase()) {
+                Some(name) if !name.is_empty() => match add_role(conn, name) {
+                    Some(role) => pkt.payload = vec![serde_json::to_string(&role).unwrap()],
+                    None => pkt = pkt.error(s!("Role add failed")),
+                },
+                _ => pkt = pkt.error(s!("Malformed role information")),
+            }
+
+            pkt
+        }
+        168 => {
+            match pkt.payload.first().cloned() {
+                Some(name) if name == "admin" => pkt = pkt.error(s!("Cannot remove admin role")),
+                Some(name) => match remove_role(conn, name) {
+                    Ok(()) => pkt.payload = vec![s!("Role removed")],
+                    Err(e) => pkt = pkt.error(e),
+                },
+                None => pkt = pkt.error(s!("Malformed role information")),
+            }
+
+            pkt
+        }
+        169 => {
+            match pkt
+                .payload
+                .first()
+                .and_then(|p| serde_json::from_str::<Permission>(p).ok())
+            {
+                Some(p) if p.first_cmd > p.last_cmd => {
+                    pkt = pkt.error(s!("first_cmd must not be above last_cmd"))
+                }
+                Some(p) if !role_exists(conn, p.role) || p.role == NO_ROLE => {
+                    pkt = pkt.error(s!("Role does not exist"))
+                }
+                Some(p) => {
+                    if add_permission(conn, p) {
+                        pkt.payload = vec![s!("Permission added")];
+                    } else {
+                        pkt = pkt.error(s!("Permission add failed"));
+                    }
+                }
+                None => pkt = pkt.error(s!("Malformed permission information")),
+            }
+
+            pkt
+        }
+        170 => {
+            match pkt
+                .payload
+                .first()
+                .and_then(|p| serde_json::from_str::<Permission>(p).ok())
+            {
+                Some(p) => {
+                    if remove_permission(conn, p) {
+                        pkt.payload = vec![s!("Permission removed")];
+                    } else {
+                        pkt = pkt.error(s!("Permission not found"));
+                    }
+                }
+                None => pkt = pkt.error(s!("Malformed permission information")),
+            }
+
+            pkt
+        }
+        _ => pkt,
+    }
+}
+
+/// Create the default roles and policy in a new database
+pub fn create_defaults(conn: &Connection) -> rusqlite::Result<()> {
+    for role in default_roles() {
+        conn.execute(
+            "INSERT INTO roles (id, name) VALUES (?1, ?2)",
+            params![role.id, role.name],
+        )?;
+    }
+    for p in default_permissions() {
+        add_permission(conn, p);
+    }
+
+    Ok(())
+}
+
+/// Get every role and permission, used by auth_svc to authorize commands
+/// cmd_type = 166
+pub fn get_policy(conn: &Connection) -> Policy {
+    let mut policy = Policy::default();
+
+    let mut stmt = conn.prepare("SELECT id, name FROM roles").unwrap();
+    let mut rows = stmt.query([]).unwrap();
+    while let Some(row) = rows.next().unwrap() {
+        policy.roles.push(Role {
+            id: row.get(0).unwrap(),
+            name: row.get(1).unwrap(),
+        })
+    }
+
+    let mut stmt = conn
+        .prepare("SELECT role, first_cmd, last_cmd FROM permissions")
+        .unwrap();
+    let mut rows = stmt.query([]).unwrap();
+    while let Some(row) = rows.next().unwrap() {
+        policy.permissions.push(Permission {
+            role: row.get(0).unwrap(),
+            first_cmd: row.get(1).unwrap(),
+            last_cmd: row.get(2).unwrap(),
+        })
+    }
+
+    policy
+}
+
+/// Add a role with the lowest free id, return the new role
+/// cmd_type = 167
+pub fn add_role(conn: &Connection, name: String) -> Option<Role> {
+    let id = (FIRST_CUSTOM_ROLE..ADMIN_ROLE).find(|id| !role_exists(conn, *id))?;
+
+    match conn.execute(
+        "INSERT INTO roles (id, name) VALUES (?1, ?2)",
+        params![id, name],
+    ) {
+        Ok(_) => Some(Role { id, name }),
+        Err(_) => None,
+    }
+}
+
+/// Remove a role and its permissions, refused while a user still has the role
+/// cmd_type = 168
+pub fn remove_role(conn: &Connection, name: String) -> Result<(), String> {
+    let id: u8 = conn
+        .query_row(
+            "SELECT id FROM roles WHERE name = (?1)",
+            params![name],
+            |row| row.get(0),
+        )
+        .map_err(|_| s!("Role does not exist"))?;
+
+    let users: u32 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM users WHERE ugroup = (?1)",
+            params![id],
+            |row| row.get(0),
+        )
+        .map_err(|_| s!("Role remove failed"))?;
+    if users > 0 {
+        return Err(format!("Role is still held by {} user(s)", users));
+    }
+
+    match conn
+        .execute("DELETE FROM permissions WHERE role = (?1)", params![id])
+        .and_then(|_| conn.execute("DELETE FROM roles WHERE id = (?1)", params![id]))
+    {
+        Ok(_) => Ok(()),
+        Err(_) => Err(s!("Role remove failed")),
+    }
+}
+
+/// Allow a role to use a range of commands
+/// cmd_type = 169
+pub fn add_permission(conn: &Connection, p: Permission) -> bool {
+    conn.execute(
+        "INSERT INTO permissions (role, first_cmd, last_cmd) VALUES (?1, ?2, ?3)",
+        params![p.role, p.first_cmd, p.last_cmd],
+    )
+    .is_ok()
+}
+
+/// Remove a permission, matched exactly
+/// cmd_type = 170
+pub fn remove_permission(conn: &Connection, p: Permission) -> bool {
+    match conn.execute(
+        "DELETE FROM permissions WHERE role = (?1) AND first_cmd = (?2) AND last_cmd = (?3)",
+        params![p.role, p.first_cmd, p.last_cmd],
+    ) {
+        Ok(removed) => removed > 0,
+        Err(_) => false,
+    }
+}
+
+pub fn role_exists(conn: &Connection, id: u8) -> bool {
+    conn.query_row("SELECT id FROM roles WHERE id = (?1)", params![id], |row| {
+        row.get::<usize, u8>(0)
+    })
+    .is_ok()
+}
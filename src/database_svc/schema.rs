@@ -19,6 +19,26 @@ pub fn cleanup(conn: &Connection) -> Result<()> {
         Ok(_) => println!("database_svc: dropping table users"),
         Err(e) => eprintln!("database_svc: ERROR could not drop users, {}", e),
     };
+    match conn.execute("DROP TABLE IF EXISTS roles", []) {
+        Ok(_) => println!("database_svc: dropping table roles"),
+        Err(e) => eprintln!("database_svc: ERROR could not drop roles, {}", e),
+    };
+    match conn.execute("DROP TABLE IF EXISTS permissions", []) {
+        Ok(_) => println!("database_svc: dropping table permissions"),
+        Err(e) => eprintln!("database_svc: ERROR could not drop permissions, {}", e),
+    };
+    match conn.execute("DROP TABLE IF EXISTS device_messages", []) {
+        Ok(_) => println!("database_svc: dropping table device_messages"),
+        Err(e) => eprintln!("database_svc: ERROR could not drop device_messages, {}", e),
+    };
+    match conn.execute("DROP TABLE IF EXISTS audit_log", []) {
+        Ok(_) => println!("database_svc: dropping table audit_log"),
+        Err(e) => eprintln!("database_svc: ERROR could not drop audit_log, {}", e),
+    };
+    match conn.execute("DROP TABLE IF EXISTS trips", []) {
+        Ok(_) => println!("database_svc: dropping table trips"),
+        Err(e) => eprintln!("database_svc: ERROR could not drop trips, {}", e),
+    };
 
     Ok(())
 }
@@ -72,6 +92,94 @@ pub fn create(conn: &Connection) -> Result<()> {
         Err(e) => eprintln!("database_svc: ERROR telemetry table was not created, {}", e),
     };
 
+    // create roles table
+    match conn.execute(
+        "CREATE TABLE roles (
+                id          INTEGER PRIMARY KEY,
+                name        TEXT UNIQUE
+                )",
+        [],
+    ) {
+        Ok(_) => println!("database_svc: roles table created"),
+        Err(e) => eprintln!("database_svc: ERROR roles table was not created, {}", e),
+    };
+
+    // create permissions table
+    match conn.execute(
+        "CREATE TABLE permissions (
+                role        INTEGER,
+                first_cmd   INTEGER,
+                last_cmd    INTEGER
+                )",
+        [],
+    ) {
+        Ok(_) => println!("database_svc: permissions table created"),
+        Err(e) => eprintln!(
+            "database_svc: ERROR permissions table was not created, {}",
+            e
+        ),
+    };
+
+    // generate default roles and policy
+    match super::roles::create_defaults(conn) {
+        Ok(_) => println!("database_svc: default roles created"),
+        Err(e) => eprintln!("database_svc: ERROR creating default roles, {}", e),
+    }
+
+    // create device_messages table
+    match conn.execute(
+        "CREATE TABLE device_messages (
+                id          INTEGER PRIMARY KEY,
+                time        INTEGER,
+                device_id   TEXT,
+                severity    TEXT,
+                code        INTEGER,
+                message     TEXT
+                )",
+        [],
+    ) {
+        Ok(_) => println!("database_svc: device_messages table created"),
+        Err(e) => eprintln!(
+            "database_svc: ERROR device_messages table was not created, {}",
+            e
+        ),
+    };
+
+    // create audit_log table
+    match conn.execute(
+        "CREATE TABLE audit_log (
+                id          INTEGER PRIMARY KEY,
+                time        INTEGER,
+                username    TEXT,
+                role        INTEGER,
+                client_addr TEXT,
+                cmd_type    INTEGER,
+                summary     TEXT,
+                outcome     TEXT
+                )",
+        [],
+    ) {
+        Ok(_) => println!("database_svc: audit_log table created"),
+        Err(e) => eprintln!("database_svc: ERROR audit_log table was not created, {}", e),
+    };
+
+    // create trips table
+    match conn.execute(
+        "CREATE TABLE trips (
+                id          INTEGER PRIMARY KEY,
+                time        INTEGER,
+                username    TEXT,
+                role        INTEGER,
+                sid         TEXT,
+                distance    REAL,
+                max_speed   REAL
+                )",
+        [],
+    ) {
+        Ok(_) => println!("database_svc: trips table created"),
+        Err(e) => eprintln!("database_svc: ERROR trips table was not created, {}", e),
+    };
+
     // create users table
     match conn.execute(
         "CREATE TABLE users (
@@ -0,0 +1,20 @@
+use rusqlite::{params, Connection};
+
+use crate::trip_svc::TripRecord;
+
+/// Store a launched trip along with the operator who launched it, passed on by trip_svc
+pub fn add_trip(conn: &Connection, trip: &TripRecord) -> bool {
+    conn.execute(
+        "INSERT INTO trips (time, username, role, sid, distance, max_speed)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            trip.time,
+            trip.username,
+            trip.role,
+            trip.sid,
+            trip.distance,
+            trip.max_speed
+        ],
+    )
+    .is_ok()
+}
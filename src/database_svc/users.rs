@@ -1,16 +1,20 @@
 use rusqlite::{params, Connection};
 
-use super::super::{user::*, RemotePacket};
+use super::super::{role::NO_ROLE, user::*, RemotePacket};
+use super::roles::role_exists;
 use shared::user::*;
 
 /// Handler for all user-related database cmd_types
-pub fn handler(conn: &Connection, mut pkt: RemotePacket) -> RemotePacket {
+/// The sender may not remove their own account or change their own role
+pub fn handler(conn: &Connection, mut pkt: RemotePacket, sender: &Identity) -> RemotePacket {
     match pkt.cmd_type {
         160 => {
             if let Ok(user) = serde_json::from_str::<UserRaw>(&pkt.payload[0]) {
                 let user = User::new(user.name, user.pwd, user.ugroup);
 
-                if add_user(conn, user) {
+                if user.ugroup != NO_ROLE && !role_exists(conn, user.ugroup) {
+                    pkt = pkt.error(s!("Role does not exist"));
+                } else if add_user(conn, user) {
                     pkt.payload[0] = s!("User added");
                 } else {
                     pkt = pkt.error(s!("User add failed"));
@@ -38,6 +42,8 @@ pub fn handler(conn: &Connection, mut pkt: RemotePacket) -> RemotePacket {
         163 => {
             if pkt.payload[0] == "admin" {
                 pkt = pkt.error(s!("Cannot remove admin account"));
+            } else if pkt.payload[0].to_lowercase() == sender.username {
+                pkt = pkt.error(s!("Cannot remove your own account"));
             } else {
                 if remove_user(&conn, pkt.payload[0].clone()) {
                     pkt.payload[0] = s!("User removed");
@@ -52,6 +58,10 @@ pub fn handler(conn: &Connection, mut pkt: RemotePacket) -> RemotePacket {
             if let Ok(user) = serde_json::from_str::<UserSecure>(&pkt.payload[0]) {
                 if user.name == "admin" {
                     pkt = pkt.error(s!("Cannot change admin account permissions"))
+                } else if user.name.to_lowercase() == sender.username {
+                    pkt = pkt.error(s!("Cannot change your own permissions"))
+                } else if user.ugroup != NO_ROLE && !role_exists(conn, user.ugroup) {
+                    pkt = pkt.error(s!("Role does not exist"))
                 } else {
                     if update_user_group(&conn, user) {
                         pkt.payload[0] = s!("User group updated");
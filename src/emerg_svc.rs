@@ -3,6 +3,7 @@ use tokio::sync::{mpsc::Receiver, mpsc::Sender};
 
 pub struct EmergSvc {
     pub rx_remote: Receiver<u8>,
+    pub rx_fault: Receiver<String>,
 
     pub rx_pod: Receiver<u8>,
     pub tx_pod: Sender<u8>, //pub rx_tele : Receiver<Packet>,
@@ -15,24 +16,11 @@ impl EmergSvc {
 
         loop {
             tokio::select! {
-                _ = self.rx_remote.recv() => {
-                    // send to pod_conn_svc to engage breaks
-                    // pod_conn_svc will engage breaks if PodState::Moving
-                    // print line listing outcome Breaks Engaged, Not Moving
-                    match self.tx_pod.send(1).await {
-                        Ok(()) => {
-                            let resp = self.rx_pod.recv().await;
-                            match resp.unwrap() {
-                                0 => println!("emerg_svc: braking unnecessary, pod_state != Moving"),
-                                1 => println!("emerg_svc: brakes engaging"),
-                                _ => println!("???")
-                            }
-                        },
-                        Err(e) => {
-
-                            eprintln!("emerg->pod failed: {}", e)
-                        }
-                    }
+                _ = self.rx_remote.recv() => self.request_braking().await,
+                Some(reason) = self.rx_fault.recv() => {
+                    // device fault reported by pod_conn_svc
+                    eprintln!("emerg_svc: {}", reason);
+                    self.request_braking().await;
                 }
                 /*_ = self.rx_tele.recv() => {
                     // unsafe conditions met in telemetry_svc
@@ -42,4 +30,21 @@ impl EmergSvc {
             }
         }
     }
+
+    /// Send to pod_conn_svc to engage breaks
+    /// pod_conn_svc will engage breaks if PodState::Moving
+    /// print line listing outcome Breaks Engaged, Not Moving
+    async fn request_braking(&mut self) {
+        match self.tx_pod.send(1).await {
+            Ok(()) => {
+                let resp = self.rx_pod.recv().await;
+                match resp.unwrap() {
+                    0 => println!("emerg_svc: braking unnecessary, pod_state != Moving"),
+                    1 => println!("emerg_svc: brakes engaging"),
+                    _ => println!("???"),
+                }
+            }
+            Err(e) => eprintln!("emerg->pod failed: {}", e),
+        }
+    }
 }
@@ -0,0 +1,8 @@
+//! Device protocol shared by the pod and the device emulator
+
+#[macro_use]
+mod macros;
+
+pub mod pod_frame;
+pub mod pod_packet;
+pub mod pod_packet_payload;
@@ -1,20 +1,29 @@
 use anyhow::Result;
 use serde_json;
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 use tokio::sync::{mpsc::Receiver, mpsc::Sender, Mutex};
 
 use crate::{
-    pod_conn_svc::PodState,
+    pod_conn_svc::{
+        announce::DeviceCandidate, secure_link::LinkSecurity, CommandSchema, DeviceStatus,
+        PodState, Subscription, MAX_PUSH_RATE_HZ,
+    },
     pod_packet::PodPacket,
-    pod_packet_payload::{encode_payload, PodPacketPayload},
+    pod_packet_payload::{encode_payload, FieldValue, PodPacketPayload},
+    user::Identity,
 };
 use shared::{device::Device, remote_conn_packet::RemotePacket};
 
 pub struct LinkSvc {
     pub device_list: Arc<Mutex<Vec<Device>>>,
     pub pod_state: Arc<Mutex<PodState>>,
+    pub device_status: Arc<Mutex<HashMap<String, DeviceStatus>>>,
+    pub device_commands: Arc<Mutex<HashMap<String, Vec<CommandSchema>>>>,
+    pub device_security: Arc<Mutex<HashMap<String, LinkSecurity>>>,
+    pub device_candidates: Arc<Mutex<HashMap<String, DeviceCandidate>>>,
+    pub device_subscriptions: Arc<Mutex<HashMap<String, Subscription>>>,
 
-    pub rx_auth: Receiver<RemotePacket>,
+    pub rx_auth: Receiver<(RemotePacket, Identity)>,
     pub tx_auth: Sender<RemotePacket>,
     pub rx_pod: Receiver<PodPacket>,
     pub tx_pod: Sender<PodPacket>,
@@ -28,7 +37,12 @@ impl LinkSvc {
         println!("link_svc: service running");
         //self.populate_temp_data().await;
 
-        while let Some(mut pkt) = self.rx_auth.recv().await {
+        while let Some((mut pkt, identity)) = self.rx_auth.recv().await {
+            println!(
+                "link_svc: command {} from {}",
+                pkt.cmd_type, identity.username
+            );
+
             // process response based on cmd_type variable
             let res = match pkt.cmd_type {
                 //32 is the beginning of the command space for link_svc
@@ -36,10 +50,48 @@ impl LinkSvc {
                 33 => self.add_device(pkt.payload[0].clone()).await.unwrap(),
                 34 => self.update_device(pkt.payload[0].clone()).await.unwrap(),
                 35 => self.remove_device(pkt.payload[0].clone()).await.unwrap(),
-                36 => self
-                    .send_device_cmd(pkt.target_cmd_code, pkt.payload[0].clone())
-                    .await
-                    .unwrap(),
+                36 => match pkt.payload.first() {
+                    Some(dev) => self
+                        .send_device_cmd(
+                            pkt.target_cmd_code,
+                            dev.clone(),
+                            pkt.payload.get(1).cloned(),
+                        )
+                        .await
+                        .unwrap_or_else(|_| s!["Malformed device command"]),
+                    _ => s!["Malformed device command"],
+                },
+                37 => self.get_device_status().await.unwrap(),
+                38 => match (pkt.payload.first(), pkt.payload.get(1)) {
+                    (Some(dev), Some(critical)) => self
+                        .set_device_critical(dev.clone(), critical.clone())
+                        .await
+                        .unwrap_or_else(|_| s!["Malformed device criticality"]),
+                    _ => s!["Malformed device criticality"],
+                },
+                39 => self.get_device_commands().await.unwrap(),
+                40 => match (pkt.payload.first(), pkt.payload.get(1)) {
+                    (Some(dev), Some(security)) => self
+                        .set_device_security(dev.clone(), security.clone())
+                        .await
+                        .unwrap_or_else(|_| s!["Malformed device security"]),
+                    _ => s!["Malformed device security"],
+                },
+                41 => self.get_device_candidates().await.unwrap(),
+                42 => match pkt.payload.first() {
+                    Some(dev) => self
+                        .adopt_device(dev.clone())
+                        .await
+                        .unwrap_or_else(|_| s!["Malformed device information"]),
+                    None => s!["Malformed device information"],
+                },
+                43 => match (pkt.payload.first(), pkt.payload.get(1)) {
+                    (Some(dev), Some(sub)) => self
+                        .set_device_subscription(dev.clone(), sub.clone())
+                        .await
+                        .unwrap_or_else(|_| s!["Malformed device subscription"]),
+                    _ => s!["Malformed device subscription"],
+                },
                 62 => self.unlock_pod().await.unwrap(),
                 63 => self.lock_pod().await.unwrap(),
                 //63 is the end of the command space for link_svc
@@ -96,13 +148,166 @@ impl LinkSvc {
         }
     }
 
+    /// Return devices that announced themselves on the pod network
+    /// and are not in device_list yet
+    async fn get_device_candidates(&self) -> Result<String, serde_json::Error> {
+        println!("link_svc: get_device_candidates command received");
+        let device_list = self.device_list.lock().await.clone();
+        let candidates: Vec<DeviceCandidate> = self
+            .device_candidates
+            .lock()
+            .await
+            .values()
+            .filter(|c| !c.is_stale() && !device_list.iter().any(|d| d.id == c.id))
+            .cloned()
+            .collect();
+
+        serde_json::to_string(&candidates)
+    }
+
+    /// Add an announced device to device list
+    /// The device received from the client names the candidate by id,
+    /// its ip address and port are taken from the device's announcement
+    ///
+    /// Return success message to client
+    async fn adopt_device(&mut self, req: String) -> Result<String, serde_json::Error> {
+        //only follow through with the command if pod is in Unlocked state
+        if self.pod_is_unlocked().await {
+            println!("link_svc: adopt_device command received");
+            let mut dev: Device = serde_json::from_str(&req)?;
+
+            let candidate = match self.device_candidates.lock().await.get(&dev.id) {
+                Some(candidate) => candidate.clone(),
+                None => return Ok(s!["Device has not announced itself"]),
+            };
+
+            let mut device_list = self.device_list.lock().await;
+            if device_list.iter().any(|d| d.id == dev.id) {
+                return Ok(s!["Device already added"]);
+            }
+            dev.ip_address = candidate.ip_address;
+            dev.port = candidate.port;
+            device_list.push(dev);
+            println!("link_svc: device adopted");
+
+            Ok(s!["Device adopted"])
+        }
+        //otherwise, return a failure message
+        else {
+            Ok(s!["Pod must be unlocked first"])
+        }
+    }
+
     async fn get_device_list(&self) -> Result<String, serde_json::Error> {
         println!("link_svc: get_device_list command received");
         serde_json::to_string(&self.device_list.lock().await.clone())
     }
 
+    /// Return the connection status of every device, keyed by device id
+    /// Devices that have never been connected are reported as Disconnected
+    async fn get_device_status(&self) -> Result<String, serde_json::Error> {
+        println!("link_svc: get_device_status command received");
+        let device_list = self.device_list.lock().await.clone();
+        let device_status = self.device_status.lock().await;
+        let status: HashMap<String, DeviceStatus> = device_list
+            .iter()
+            .map(|d| {
+                (
+                    d.id.clone(),
+                    device_status.get(&d.id).cloned().unwrap_or_default(),
+                )
+            })
+            .collect();
+
+        serde_json::to_string(&status)
+    }
+
+    /// Mark a device as critical or not
+    /// A critical device that misses a heartbeat while Moving triggers emergency braking
+    /// Devices are not critical until marked, e.g. brake controllers should be
+    async fn set_device_critical(
+        &mut self,
+        req: String,
+        critical: String,
+    ) -> Result<String, serde_json::Error> {
+        println!("link_svc: set_device_critical command received");
+        let dev: Device = serde_json::from_str(&req)?;
+        let critical: bool = serde_json::from_str(&critical)?;
+        self.device_status
+            .lock()
+            .await
+            .entry(dev.id)
+            .or_default()
+            .critical = critical;
+
+        Ok(s!["Device criticality updated"])
+    }
+
+    /// Set how the link to a device is secured, stored alongside the device configuration
+    /// Like device_list it is kept in memory only and has to be set again after a restart
+    /// Takes effect the next time the pod is locked
+    async fn set_device_security(
+        &mut self,
+        req: String,
+        security: String,
+    ) -> Result<String, serde_json::Error> {
+        //only follow through with the command if pod is in Unlocked state
+        if self.pod_is_unlocked().await {
+            println!("link_svc: set_device_security command received");
+            let dev: Device = serde_json::from_str(&req)?;
+            let security: LinkSecurity = serde_json::from_str(&security)?;
+            self.device_security.lock().await.insert(dev.id, security);
+
+            Ok(s!["Device security updated"])
+        }
+        //otherwise, return a failure message
+        else {
+            Ok(s!["Pod must be unlocked first"])
+        }
+    }
+
+    /// Set the telemetry fields a device pushes and how many times a second
+    /// A rate of 0 or an empty field list goes back to polling the device
+    /// Takes effect the next time the pod is locked
+    async fn set_device_subscription(
+        &mut self,
+        req: String,
+        subscription: String,
+    ) -> Result<String, serde_json::Error> {
+        //only follow through with the command if pod is in Unlocked state
+        if self.pod_is_unlocked().await {
+            println!("link_svc: set_device_subscription command received");
+            let dev: Device = serde_json::from_str(&req)?;
+            let sub: Subscription = serde_json::from_str(&subscription)?;
+
+            if sub.rate_hz == 0 || sub.fields.is_empty() {
+                self.device_subscriptions.lock().await.remove(&dev.id);
+                return Ok(s!["Device subscription removed"]);
+            }
+            if sub.rate_hz > MAX_PUSH_RATE_HZ {
+                return Ok(format!("Rate may not exceed {}Hz", MAX_PUSH_RATE_HZ));
+            }
+            self.device_subscriptions.lock().await.insert(dev.id, sub);
+
+            Ok(s!["Device subscription updated"])
+        }
+        //otherwise, return a failure message
+        else {
+            Ok(s!["Pod must be unlocked first"])
+        }
+    }
+
+    /// Return the device specific commands and their arguments, keyed by device id
+    /// Only devices that have completed discovery are included
+    async fn get_device_commands(&self) -> Result<String, serde_json::Error> {
+        println!("link_svc: get_device_commands command received");
+        serde_json::to_string(&*self.device_commands.lock().await)
+    }
+
     /// Lock device_list to start TCP connections to embedded devices in pod_conn_svc
     /// Once locked, devices cannot be edited in "Configure" page until the pod is unlocked
+    ///
+    /// Return the discovered device list, or the reason each device blocked locking
     async fn lock_pod(&mut self) -> Result<String, serde_json::Error> {
         println!("link_svc: lock_devices command received");
 
@@ -118,10 +323,15 @@ impl LinkSvc {
         }
 
         // if lock command was successful
-        match self.rx_pod.recv().await.unwrap().cmd_type {
+        let resp = self.rx_pod.recv().await.unwrap();
+        match resp.cmd_type {
             0 => {
-                // return new device_list
-                self.get_device_list().await
+                // return the reason for each device that could not be connected or discovered
+                // sent by pod_conn_svc as a JSON object keyed by device id
+                match String::from_utf8(resp.payload) {
+                    Ok(errors) if errors.starts_with('{') => Ok(format!("Lock failed: {}", errors)),
+                    _ => Ok(s!["Lock failed"]),
+                }
             }
             _ => {
                 // return new device_list
@@ -175,6 +385,8 @@ impl LinkSvc {
                 .position(|d| d.id == dev.id)
                 .unwrap();
             self.device_list.lock().await.remove(index);
+            self.device_security.lock().await.remove(&dev.id);
+            self.device_subscriptions.lock().await.remove(&dev.id);
             println!("link_svc: device removed");
 
             Ok(s!["Device removed"])
@@ -211,22 +423,39 @@ impl LinkSvc {
         }
     }
 
-    /// Get device and command code from client
+    /// Get device, command code and optional arguments from client
+    /// Arguments are a JSON array in declared order, e.g. [0.4] or [80, true]
     /// Send the command to the corresponding device in device list
+    ///
+    /// Return the device's result to the client: success, error code, returned values and timing
     async fn send_device_cmd(
         &mut self,
         cmd_code: u8,
         req: String,
+        args: Option<String>,
     ) -> Result<String, serde_json::Error> {
         println!("link_svc: send_device_cmd command received");
 
         //recontruct the Device instance from the payload
         let dev: Device = serde_json::from_str(&req)?;
 
-        //construct a payload that specifies the target device and target cmd code
+        //the arguments are converted to their declared types by pod_conn_svc
+        let args: Vec<serde_json::Value> = match args.as_deref().map(serde_json::from_str) {
+            Some(Ok(args)) => args,
+            Some(Err(_)) => return Ok(s!["Arguments must be a JSON array"]),
+            None => Vec::new(),
+        };
+        let args: Option<Vec<FieldValue>> = args.iter().map(json_to_value).collect();
+        let args = match args {
+            Some(args) => args,
+            None => return Ok(s!["Arguments must be numbers, booleans or strings"]),
+        };
+
+        //construct a payload that specifies the target device, target cmd code and arguments
         let mut payload = PodPacketPayload::new();
         payload.target_id = dev.id;
         payload.target_cmd_code = cmd_code;
+        payload.args = args;
 
         //tell pod_conn_svc to send the command to the appropriate device
         if let Err(e) = self
@@ -237,9 +466,33 @@ impl LinkSvc {
             //if unsuccessful
             //return error message
             println!("link->pod failed: {}", e);
+            return Ok(s!["Cmd could not be sent to device"]);
         }
 
-        //return success message
-        Ok(s!["Cmd sent to device"])
+        //return the device's answer, a CommandResult serialized by pod_conn_svc
+        match self
+            .rx_pod
+            .recv()
+            .await
+            .map(|pkt| String::from_utf8(pkt.payload))
+        {
+            Some(Ok(result)) => Ok(result),
+            Some(Err(_)) => Ok(s!["Invalid result from device"]),
+            None => Ok(s!["Cmd could not be sent to device"]),
+        }
+    }
+}
+
+/// Convert a command argument supplied by the client to a loosely typed value
+fn json_to_value(value: &serde_json::Value) -> Option<FieldValue> {
+    match value {
+        serde_json::Value::Bool(v) => Some(FieldValue::Bool(*v)),
+        serde_json::Value::String(v) => Some(FieldValue::Str(v.clone())),
+        serde_json::Value::Number(v) => match (v.as_i64(), v.as_u64()) {
+            (Some(v), _) => Some(FieldValue::I64(v)),
+            (None, Some(v)) => Some(FieldValue::U64(v)),
+            _ => v.as_f64().map(FieldValue::F64),
+        },
+        _ => None,
     }
 }
@@ -1,2 +1,3 @@
 /// Macro to simplify .to_string()
+#[macro_export]
 macro_rules! s( ($e:expr) => ( ($e).to_string() ) );
@@ -1,5 +1,9 @@
 use anyhow::Result;
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::Arc,
+};
 use tokio::{
     spawn,
     sync::{mpsc, Mutex},
@@ -8,21 +12,33 @@ use tokio::{
 #[macro_use]
 mod macros;
 
+mod audit;
 mod auth_svc;
+mod clock;
 mod ctrl_svc;
 mod database_svc;
 mod emerg_svc;
 mod link_svc;
 mod pod_conn_svc;
-mod pod_packet;
-mod pod_packet_payload;
 mod remote_conn_svc;
+mod role;
 mod tele_svc;
 mod trip_svc;
 mod user;
 
+use openlink_pod::{pod_frame, pod_packet, pod_packet_payload};
+use pod_conn_svc::{
+    announce::DeviceCandidate,
+    device_conn::{DeviceEvent, PushedTelemetry},
+    secure_link::LinkSecurity,
+    CommandSchema, DeviceMessage, DeviceStatus, Subscription, TelemetrySample,
+};
 use pod_packet::PodPacket;
-use shared::{device::Device, launch::LaunchParams, remote_conn_packet::RemotePacket};
+use shared::{
+    device::Device, launch::LaunchParams, remote_conn_packet::RemotePacket,
+    telemetry::TelemetryData,
+};
+use user::Identity;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -30,14 +46,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // auth-remote
     let (tx_auth_to_remote, rx_auth_to_remote) = mpsc::channel::<RemotePacket>(32);
-    let (tx_remote_to_auth, rx_remote_to_auth) = mpsc::channel::<RemotePacket>(32);
+    let (tx_remote_to_auth, rx_remote_to_auth) = mpsc::channel::<(RemotePacket, SocketAddr)>(32);
 
     // auth-link
-    let (tx_auth_to_link, rx_auth_to_link) = mpsc::channel::<RemotePacket>(32);
+    let (tx_auth_to_link, rx_auth_to_link) = mpsc::channel::<(RemotePacket, Identity)>(32);
     let (tx_link_to_auth, rx_link_to_auth) = mpsc::channel::<RemotePacket>(32);
 
     // auth-ctrl
-    let (tx_auth_to_ctrl, rx_auth_to_ctrl) = mpsc::channel::<RemotePacket>(32);
+    let (tx_auth_to_ctrl, rx_auth_to_ctrl) = mpsc::channel::<(RemotePacket, Identity)>(32);
     let (tx_ctrl_to_auth, rx_ctrl_to_auth) = mpsc::channel::<RemotePacket>(32);
 
     // remote-emerg (only one channel needed because nothing is being sent back to client)
@@ -51,24 +67,54 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let (tx_pod_to_emerg, rx_pod_to_emerg) = mpsc::channel::<u8>(32);
     let (tx_emerg_to_pod, rx_emerg_to_pod) = mpsc::channel::<u8>(32);
 
+    // pod-emerg device faults (separate so faults are never mistaken for braking responses)
+    let (tx_fault_to_emerg, rx_fault_to_emerg) = mpsc::channel::<String>(32);
+
     // link-pod
     let (tx_link_to_pod, rx_link_to_pod) = mpsc::channel::<PodPacket>(32);
     let (tx_pod_to_link, rx_pod_to_link) = mpsc::channel::<PodPacket>(32);
 
+    // device connections-pod
+    let (tx_device_to_pod, rx_device_to_pod) = mpsc::channel::<DeviceEvent>(32);
+
+    // device connections-pod pushed telemetry (separate so pushes cannot crowd out heartbeats and faults)
+    let (tx_push_to_pod, rx_push_to_pod) =
+        mpsc::channel::<PushedTelemetry>(pod_conn_svc::PUSH_QUEUE_SIZE);
+
     // auth-data
-    let (tx_auth_to_data, rx_auth_to_data) = mpsc::channel::<RemotePacket>(32);
+    let (tx_auth_to_data, rx_auth_to_data) = mpsc::channel::<(RemotePacket, Identity)>(32);
     let (tx_data_to_auth, rx_data_to_auth) = mpsc::channel::<RemotePacket>(32);
 
+    // auth-data audit log entries
+    let (tx_audit_to_data, rx_audit_to_data) = mpsc::channel::<audit::AuditEntry>(32);
+
     // auth-tele
-    let (tx_auth_to_tele, rx_auth_to_tele) = mpsc::channel::<RemotePacket>(32);
+    let (tx_auth_to_tele, rx_auth_to_tele) = mpsc::channel::<(RemotePacket, Identity)>(32);
     let (tx_tele_to_auth, rx_tele_to_auth) = mpsc::channel::<RemotePacket>(32);
 
-    // ctrl-trip
-    let (tx_ctrl_to_trip, rx_ctrl_to_trip) = mpsc::channel::<LaunchParams>(32);
+    // tele-pod
+    let (tx_tele_to_pod, rx_tele_to_pod) = mpsc::channel::<u8>(32);
+    let (tx_pod_to_tele, rx_pod_to_tele) = mpsc::channel::<Vec<TelemetryData>>(32);
+
+    // pod-tele device messages (separate so messages are never mistaken for telemetry replies)
+    let (tx_msg_to_tele, rx_msg_to_tele) = mpsc::channel::<DeviceMessage>(32);
+
+    // pod-tele pushed telemetry
+    let (tx_push_to_tele, rx_push_to_tele) =
+        mpsc::channel::<Vec<TelemetrySample>>(pod_conn_svc::PUSH_QUEUE_SIZE);
+
+    // tele-data
+    let (tx_tele_to_data, rx_tele_to_data) = mpsc::channel::<DeviceMessage>(32);
+
+    // ctrl-trip, each trip goes with the operator who launched it
+    let (tx_ctrl_to_trip, rx_ctrl_to_trip) = mpsc::channel::<(LaunchParams, Identity)>(32);
 
     // trip-pod
     let (tx_trip_to_pod, rx_trip_to_pod) = mpsc::channel::<u8>(32);
 
+    // trip-data
+    let (tx_trip_to_data, rx_trip_to_data) = mpsc::channel::<trip_svc::TripRecord>(32);
+
     // shared memory
     let device_list: Vec<Device> = Vec::new();
     let device_list = Arc::new(Mutex::new(device_list));
@@ -77,9 +123,25 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         max_speed: None,
     };
     let pod_state = Arc::new(Mutex::new(pod_conn_svc::PodState::Unlocked));
+    let device_status: HashMap<String, DeviceStatus> = HashMap::new();
+    let device_status = Arc::new(Mutex::new(device_status));
+    let device_commands: HashMap<String, Vec<CommandSchema>> = HashMap::new();
+    let device_commands = Arc::new(Mutex::new(device_commands));
+    // in memory only, like device_list, so it has to be set again after a restart
+    let device_security: HashMap<String, LinkSecurity> = HashMap::new();
+    let device_security = Arc::new(Mutex::new(device_security));
+    let device_candidates: HashMap<String, DeviceCandidate> = HashMap::new();
+    let device_candidates = Arc::new(Mutex::new(device_candidates));
+    let device_subscriptions: HashMap<String, Subscription> = HashMap::new();
+    let device_subscriptions = Arc::new(Mutex::new(device_subscriptions));
 
     // Create services with necessary control signals
     let auth_svc = auth_svc::AuthSvc {
+        config: auth_svc::AuthConfig::from_env()?,
+        policy: role::Policy::default(),
+        sessions: HashMap::new(),
+        throttle: auth_svc::LoginThrottle::default(),
+
         rx_remote: rx_remote_to_auth,
         tx_remote: tx_auth_to_remote,
 
@@ -91,6 +153,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         rx_data: rx_data_to_auth,
         tx_data: tx_auth_to_data,
+        tx_audit: tx_audit_to_data,
 
         rx_tele: rx_tele_to_auth,
         tx_tele: tx_auth_to_tele,
@@ -101,6 +164,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         tx_pod: tx_emerg_to_pod,
 
         rx_remote: rx_remote_to_emerg,
+        rx_fault: rx_fault_to_emerg,
     };
 
     let ctrl_svc = ctrl_svc::CtrlSvc {
@@ -119,6 +183,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let link_svc = link_svc::LinkSvc {
         device_list: Arc::clone(&device_list),
         pod_state: Arc::clone(&pod_state),
+        device_status: Arc::clone(&device_status),
+        device_commands: Arc::clone(&device_commands),
+        device_security: Arc::clone(&device_security),
+        device_candidates: Arc::clone(&device_candidates),
+        device_subscriptions: Arc::clone(&device_subscriptions),
         rx_auth: rx_auth_to_link,
         tx_auth: tx_link_to_auth,
         rx_pod: rx_pod_to_link,
@@ -133,35 +202,66 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let pod_conn_svc = pod_conn_svc::PodConnSvc {
         conn_list: Vec::new(),
+        max_frame_size: pod_frame::DEFAULT_MAX_FRAME_SIZE,
+        heartbeat_interval: pod_conn_svc::DEFAULT_HEARTBEAT_INTERVAL,
+        heartbeat_timeout: pod_conn_svc::DEFAULT_HEARTBEAT_TIMEOUT,
+        cmd_timeouts: pod_conn_svc::CommandTimeouts::default(),
+        announce_port: pod_conn_svc::announce::DEFAULT_ANNOUNCE_PORT,
+        device_fields: HashMap::new(),
         device_list: Arc::clone(&device_list),
         pod_state: Arc::clone(&pod_state),
+        device_status: Arc::clone(&device_status),
+        device_commands: Arc::clone(&device_commands),
+        device_security: Arc::clone(&device_security),
+        device_candidates: Arc::clone(&device_candidates),
+        device_subscriptions: Arc::clone(&device_subscriptions),
+        rx_device: rx_device_to_pod,
+        tx_device: tx_device_to_pod,
+        rx_push: rx_push_to_pod,
+        tx_push: tx_push_to_pod,
         rx_ctrl: rx_ctrl_to_pod,
         tx_ctrl: tx_pod_to_ctrl,
         rx_emerg: rx_emerg_to_pod,
         tx_emerg: tx_pod_to_emerg,
+        tx_fault: tx_fault_to_emerg,
         rx_link: rx_link_to_pod,
         tx_link: tx_pod_to_link,
-        //rx_tele: todo!(),
-        //tx_tele: todo!(),
+        rx_tele: rx_tele_to_pod,
+        tx_tele: tx_pod_to_tele,
+        tx_tele_msg: tx_msg_to_tele,
+        tx_tele_push: tx_push_to_tele,
         rx_trip: rx_trip_to_pod,
     };
 
     let tele_svc = tele_svc::TelemetrySvc {
         pod_state: Arc::clone(&pod_state),
         tele_data: Vec::new(),
+        source: tele_svc::TelemetrySource::from_env(),
+        device_msgs: VecDeque::new(),
+        pushed_data: Vec::new(),
+        samples: VecDeque::new(),
         rx_auth: rx_auth_to_tele,
         tx_auth: tx_tele_to_auth,
+        tx_data: tx_tele_to_data,
+        rx_pod: rx_pod_to_tele,
+        tx_pod: tx_tele_to_pod,
+        rx_pod_msg: rx_msg_to_tele,
+        rx_pod_push: rx_push_to_tele,
     };
 
     let database_svc = database_svc::DatabaseSvc {
         rx_auth: rx_auth_to_data,
         tx_auth: tx_data_to_auth,
+        rx_audit: rx_audit_to_data,
+        rx_tele: rx_tele_to_data,
+        rx_trip: rx_trip_to_data,
     };
 
     let trip_svc = trip_svc::TripSvc {
         pod_state: Arc::clone(&pod_state),
         rx_ctrl: rx_ctrl_to_trip,
         tx_pod: tx_trip_to_pod,
+        tx_data: tx_trip_to_data,
     };
 
     // Spawn all services as tasks
@@ -0,0 +1,273 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+use tokio::{net::UdpSocket, sync::Mutex};
+
+use crate::clock::unix_time;
+use crate::pod_frame::{PodFrameCodec, CHECKSUM_LEN, HEADER_LEN};
+use crate::pod_packet::{decode, PodPacketError, CMD_ANNOUNCE};
+use crate::pod_packet_payload::decode_payload;
+
+/// Default UDP port device announcements are received on
+pub const DEFAULT_ANNOUNCE_PORT: u16 = 45454;
+
+/// Multicast group devices may announce to, as an alternative to broadcast
+pub const ANNOUNCE_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 45, 45);
+
+/// Candidates that have not announced themselves for this long are no longer offered to the client
+pub const CANDIDATE_TTL_SECS: u64 = 30;
+
+/// Upper bound on the number of candidates kept at once
+/// Anything on the pod network can send announcements, so new devices are ignored once it is reached
+pub const MAX_CANDIDATES: usize = 64;
+
+/// Device that has announced itself on the pod network, keyed by device id
+/// Offered to the client through link_svc until an operator adopts it into device_list
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DeviceCandidate {
+    pub id: String,
+    pub ip_address: String,
+    pub port: u16,
+    pub firmware: String,
+    pub protocol_version: u8,
+    // unix time in seconds of the latest announcement
+    pub last_seen: u64,
+}
+
+impl DeviceCandidate {
+    pub fn is_stale(&self) -> bool {
+        unix_time().saturating_sub(self.last_seen) > CANDIDATE_TTL_SECS
+    }
+}
+
+/// Receive device announcements sent by broadcast, multicast to ANNOUNCE_GROUP or unicast,
+/// and record every announcing device as a candidate
+pub async fn listen(
+    port: u16,
+    max_frame_size: usize,
+    candidates: Arc<Mutex<HashMap<String, DeviceCandidate>>>,
+) {
+    let socket = match UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port))).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("pod_conn_svc: announcement listener failed to bind: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = socket.join_multicast_v4(ANNOUNCE_GROUP, Ipv4Addr::UNSPECIFIED) {
+        eprintln!(
+            "pod_conn_svc: could not join announcement group {}: {}",
+            ANNOUNCE_GROUP, e
+        );
+    }
+    println!(
+        "pod_conn_svc: listening for device announcements on port {}",
+        port
+    );
+
+    receive(socket, max_frame_size, candidates).await
+}
+
+/// Record every announcement received on an already bound socket
+async fn receive(
+    socket: UdpSocket,
+    max_frame_size: usize,
+    candidates: Arc<Mutex<HashMap<String, DeviceCandidate>>>,
+) {
+    let mut buf = vec![0; HEADER_LEN + max_frame_size + CHECKSUM_LEN];
+    loop {
+        let (size, addr) = match socket.recv_from(&mut buf).await {
+            Ok(res) => res,
+            Err(e) => {
+                eprintln!("pod_conn_svc: announcement receive failed: {}", e);
+                continue;
+            }
+        };
+
+        match read_announcement(&buf[..size], addr, max_frame_size) {
+            Ok(candidate) => record_candidate(&mut *candidates.lock().await, candidate),
+            Err(e) => eprintln!("pod_conn_svc: bad announcement from {}: {}", addr, e),
+        }
+    }
+}
+
+/// Add or refresh a candidate, dropping stale ones first so the map stays within MAX_CANDIDATES
+fn record_candidate(candidates: &mut HashMap<String, DeviceCandidate>, candidate: DeviceCandidate) {
+    candidates.retain(|_, c| !c.is_stale());
+
+    if !candidates.contains_key(&candidate.id) {
+        if candidates.len() >= MAX_CANDIDATES {
+            eprintln!(
+                "pod_conn_svc: ignoring announcement from device {}, {} candidates already known",
+                candidate.id, MAX_CANDIDATES
+            );
+            return;
+        }
+        println!(
+            "pod_conn_svc: device {} announced at {}:{}",
+            candidate.id, candidate.ip_address, candidate.port
+        );
+    }
+    candidates.insert(candidate.id.clone(), candidate);
+}
+
+/// Decode a single announcement datagram, framed the same way as packets on a device link
+fn read_announcement(
+    datagram: &[u8],
+    addr: SocketAddr,
+    max_frame_size: usize,
+) -> Result<DeviceCandidate, PodPacketError> {
+    let mut codec = PodFrameCodec::new(max_frame_size);
+    codec.extend(datagram);
+    let body = match codec.decode_frame() {
+        Ok(Some(body)) => body,
+        Ok(None) => return Err(PodPacketError::Truncated),
+        Err(e) => match e.downcast::<PodPacketError>() {
+            Ok(e) => return Err(e),
+            Err(e) => return Err(PodPacketError::Oversized(s!(e))),
+        },
+    };
+
+    let pkt = decode(body)?;
+    if pkt.cmd_type != CMD_ANNOUNCE {
+        return Err(PodPacketError::Malformed(format!(
+            "expected announcement, received cmd {}",
+            pkt.cmd_type
+        )));
+    }
+
+    let payload = decode_payload(pkt.payload)?;
+    if payload.target_id.is_empty() || payload.port == 0 {
+        return Err(PodPacketError::Malformed(s!("missing device id or port")));
+    }
+
+    Ok(DeviceCandidate {
+        id: payload.target_id,
+        ip_address: s!(addr.ip()),
+        port: payload.port,
+        firmware: payload.firmware,
+        protocol_version: pkt.version,
+        last_seen: unix_time(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pod_frame::DEFAULT_MAX_FRAME_SIZE;
+    use crate::pod_packet::{encode, PodPacket};
+    use crate::pod_packet_payload::{encode_payload, PodPacketPayload};
+    use std::time::Duration;
+
+    fn announcement(id: &str, port: u16) -> Vec<u8> {
+        let mut payload = PodPacketPayload::new();
+        payload.target_id = s!(id);
+        payload.firmware = s!("test-1.0");
+        payload.port = port;
+
+        let pkt = PodPacket::new(CMD_ANNOUNCE, encode_payload(payload));
+        PodFrameCodec::new(DEFAULT_MAX_FRAME_SIZE)
+            .encode_frame(&encode(pkt))
+            .unwrap()
+    }
+
+    fn candidate(id: &str, last_seen: u64) -> DeviceCandidate {
+        DeviceCandidate {
+            id: s!(id),
+            ip_address: s!("127.0.0.1"),
+            port: 5000,
+            firmware: s!("test-1.0"),
+            protocol_version: 0,
+            last_seen,
+        }
+    }
+
+    #[tokio::test]
+    async fn records_announcements_over_loopback() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let candidates = Arc::new(Mutex::new(HashMap::new()));
+        let listener = tokio::spawn(receive(
+            socket,
+            DEFAULT_MAX_FRAME_SIZE,
+            Arc::clone(&candidates),
+        ));
+
+        let device = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        device.send_to(b"not an announcement", addr).await.unwrap();
+        device
+            .send_to(&announcement("brakes", 5001), addr)
+            .await
+            .unwrap();
+
+        let mut found = None;
+        for _ in 0..50 {
+            if let Some(c) = candidates.lock().await.get("brakes") {
+                found = Some(c.clone());
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        listener.abort();
+
+        let found = found.expect("announcement was not recorded");
+        assert_eq!(found.ip_address, "127.0.0.1");
+        assert_eq!(found.port, 5001);
+        assert_eq!(found.firmware, "test-1.0");
+        assert_eq!(candidates.lock().await.len(), 1);
+    }
+
+    #[test]
+    fn rejects_bad_announcements() {
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 45454));
+
+        let frame = announcement("brakes", 5001);
+        assert!(matches!(
+            read_announcement(&frame[..frame.len() - 1], addr, DEFAULT_MAX_FRAME_SIZE),
+            Err(PodPacketError::Truncated)
+        ));
+        assert!(matches!(
+            read_announcement(&announcement("", 5001), addr, DEFAULT_MAX_FRAME_SIZE),
+            Err(PodPacketError::Malformed(_))
+        ));
+        assert!(matches!(
+            read_announcement(&announcement("brakes", 0), addr, DEFAULT_MAX_FRAME_SIZE),
+            Err(PodPacketError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn drops_stale_candidates_and_caps_the_map() {
+        let mut candidates = HashMap::new();
+        let stale = unix_time() - CANDIDATE_TTL_SECS - 1;
+        for i in 0..MAX_CANDIDATES {
+            candidates.insert(
+                format!("stale{}", i),
+                candidate(&format!("stale{}", i), stale),
+            );
+        }
+
+        // stale candidates make room for new ones
+        record_candidate(&mut candidates, candidate("fresh0", unix_time()));
+        assert_eq!(candidates.len(), 1);
+
+        for i in 1..MAX_CANDIDATES {
+            record_candidate(
+                &mut candidates,
+                candidate(&format!("fresh{}", i), unix_time()),
+            );
+        }
+        assert_eq!(candidates.len(), MAX_CANDIDATES);
+
+        // a full map refuses new devices but still refreshes known ones
+        record_candidate(&mut candidates, candidate("extra", unix_time()));
+        assert!(!candidates.contains_key("extra"));
+        let mut refreshed = candidate("fresh0", unix_time());
+        refreshed.port = 6000;
+        record_candidate(&mut candidates, refreshed);
+        assert_eq!(candidates["fresh0"].port, 6000);
+    }
+}
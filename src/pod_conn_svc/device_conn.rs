@@ -0,0 +1,736 @@
+use std::{collections::HashMap, fmt, future::Future, sync::Arc};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    select, spawn,
+    sync::{
+        mpsc::{self, error::TrySendError},
+        oneshot, Mutex,
+    },
+    time::{interval, sleep_until, timeout, Duration, Instant},
+};
+
+use super::{record_dropped, record_malformed, ConnState, DeviceMessage, DeviceStatus, Severity};
+use crate::clock::unix_time_ms;
+use crate::pod_frame::FramedConn;
+use crate::pod_packet::{
+    decode, peek_seq, PodPacket, PodPacketError, CMD_CORRUPTED, CMD_HANDSHAKE, CMD_TELEMETRY,
+};
+use crate::pod_packet_payload::{decode_payload, encode_payload, PodPacketPayload};
+
+/// Number of commands that may be queued for a single device
+const QUEUE_SIZE: usize = 32;
+
+/// Delay before the first reconnect attempt, doubled after every failed attempt
+const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+
+/// Longest delay between reconnect attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(8);
+
+/// Deadline for a device to answer the connect-time handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often commands whose caller gave up waiting are dropped from the pending list
+const PURGE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Reasons a single command to a single device can fail
+#[derive(Debug)]
+pub enum DeviceError {
+    /// the connection could not be written to or read from
+    Io(String),
+    /// the device failed the handshake or speaks an unsupported protocol version
+    Incompatible(String),
+    /// the device could not be authenticated over a secured link
+    Unauthenticated(String),
+    /// the device did not reply before the command's deadline
+    TimedOut(Duration),
+    /// the device's reply could not be decoded
+    Malformed(String),
+    /// the connection dropped and is being re-established
+    Disconnected,
+    /// the connection task is no longer running
+    Closed,
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeviceError::Io(e) => write!(f, "i/o error: {}", e),
+            DeviceError::Incompatible(e) => write!(f, "incompatible device: {}", e),
+            DeviceError::Unauthenticated(e) => write!(f, "authentication failed: {}", e),
+            DeviceError::TimedOut(deadline) => {
+                write!(f, "no reply within {}ms", deadline.as_millis())
+            }
+            DeviceError::Malformed(e) => write!(f, "malformed reply: {}", e),
+            DeviceError::Disconnected => write!(f, "device reconnecting"),
+            DeviceError::Closed => write!(f, "device connection closed"),
+        }
+    }
+}
+
+/// Notifications from device connection tasks to pod_conn_svc
+pub enum DeviceEvent {
+    /// the connection to the device with this id dropped and was re-established
+    Reconnected(String),
+    /// the device answered a heartbeat after the given round trip time
+    Heartbeat(String, Duration),
+    /// the device did not answer a heartbeat before its deadline
+    HeartbeatMissed(String),
+    /// result of re-running discovery on a device after it reconnected
+    Rediscovered(String, Result<Option<PodPacket>, DeviceError>),
+    /// the device sent a fault, warning or log message on its own
+    Message(DeviceMessage),
+}
+
+/// Readings a device pushed for its telemetry subscription
+/// Sent to pod_conn_svc on a channel of their own, so a device pushing at a high rate
+/// cannot fill the queue that heartbeats and faults travel on
+pub struct PushedTelemetry {
+    pub device_id: String,
+    pub payload: PodPacketPayload,
+    // unix time in ms the readings were received at
+    pub time_ms: u64,
+}
+
+/// Identity reported by a device during the connect-time handshake
+pub struct Handshake {
+    pub version: u8,
+    pub firmware: String,
+}
+
+/// Opens the stream to a single device, and again after every dropped connection
+/// Implemented by SecureLink for the TCP and TLS links to real devices
+pub trait DeviceLink: Send + Sync + 'static {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    fn open(&self) -> impl Future<Output = Result<Self::Stream, DeviceError>> + Send;
+}
+
+/// Ask a freshly connected device for its protocol version and firmware identity
+/// The reply's version has already been checked against the versions this pod accepts by decode
+pub async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    conn: &mut FramedConn<S>,
+) -> Result<Handshake, DeviceError> {
+    let res = timeout(HANDSHAKE_TIMEOUT, async {
+        conn.send(PodPacket::new(
+            CMD_HANDSHAKE,
+            encode_payload(PodPacketPayload::new()),
+        ))
+        .await?;
+        conn.recv().await
+    })
+    .await;
+
+    let resp = match res {
+        Ok(Ok(resp)) => resp,
+        Ok(Err(e)) => {
+            return match e.downcast::<PodPacketError>() {
+                Ok(PodPacketError::Corrupted) => Err(DeviceError::Io(s!("handshake corrupted"))),
+                Ok(e) => Err(DeviceError::Incompatible(s!(e))),
+                Err(e) => Err(DeviceError::Io(s!(e))),
+            }
+        }
+        Err(_) => return Err(DeviceError::Io(s!("handshake timed out"))),
+    };
+
+    if resp.cmd_type != CMD_HANDSHAKE {
+        return Err(DeviceError::Incompatible(format!(
+            "answered handshake with cmd {}",
+            resp.cmd_type
+        )));
+    }
+
+    match decode_payload(resp.payload) {
+        Ok(payload) => Ok(Handshake {
+            version: resp.version,
+            firmware: payload.firmware,
+        }),
+        Err(e) => Err(DeviceError::Incompatible(s!(e))),
+    }
+}
+
+/// Where the result of a single command is sent
+type Responder = oneshot::Sender<Result<Option<PodPacket>, DeviceError>>;
+
+/// Command queued to a device connection task, along with where to send the result
+struct DeviceRequest {
+    packet: PodPacket,
+    expect_reply: bool,
+    resp: Responder,
+}
+
+/// Handle to the task that owns a single device connection
+///
+/// Commands are queued to the task and processed in order,
+/// while commands to different devices run concurrently.
+/// Dropping every handle ends the task and closes the connection.
+#[derive(Clone)]
+pub struct DeviceHandle {
+    pub id: String,
+    tx: mpsc::Sender<DeviceRequest>,
+    device_status: Arc<Mutex<HashMap<String, DeviceStatus>>>,
+}
+
+impl DeviceHandle {
+    /// Send a command to the device and wait for its reply, for no longer than deadline
+    /// Disconnect commands (cmd 2) do not expect a reply and return None
+    pub async fn request(
+        &self,
+        cmd: u8,
+        payload: PodPacketPayload,
+        deadline: Duration,
+    ) -> Result<Option<PodPacket>, DeviceError> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DeviceRequest {
+            packet: PodPacket::new(cmd, encode_payload(payload)),
+            expect_reply: cmd != 2,
+            resp: resp_tx,
+        };
+
+        let res = timeout(deadline, async {
+            if self.tx.send(req).await.is_err() {
+                return Err(DeviceError::Closed);
+            }
+
+            match resp_rx.await {
+                Ok(res) => res,
+                Err(_) => Err(DeviceError::Closed),
+            }
+        })
+        .await;
+
+        match res {
+            Ok(res) => res,
+            Err(_) => {
+                self.device_status
+                    .lock()
+                    .await
+                    .entry(self.id.clone())
+                    .or_default()
+                    .timeouts += 1;
+                Err(DeviceError::TimedOut(deadline))
+            }
+        }
+    }
+}
+
+/// Connection task for a single device
+///
+/// Writes each queued command to the device as soon as it arrives, tagged with a sequence number,
+/// and routes each reply back to the command whose seq it echoes,
+/// so several commands can be in flight to the same device at once.
+/// Frames the device sends on its own with seq 0 are passed to pod_conn_svc
+/// as DeviceEvent::Message or PushedTelemetry.
+/// Pushed readings are dropped rather than waited on when pod_conn_svc falls behind.
+/// If the connection drops or a frame in either direction is corrupted, commands in flight
+/// and queued commands fail immediately with DeviceError::Disconnected
+/// while the task reconnects with exponential backoff.
+pub struct DeviceConn<L: DeviceLink> {
+    pub id: String,
+    pub link: L,
+    pub max_frame_size: usize,
+
+    pub device_status: Arc<Mutex<HashMap<String, DeviceStatus>>>,
+    pub tx_event: mpsc::Sender<DeviceEvent>,
+    pub tx_push: mpsc::Sender<PushedTelemetry>,
+}
+
+impl<L: DeviceLink> DeviceConn<L> {
+    /// Spawn the task around a connection that has completed its handshake and return its handle
+    pub fn spawn(self, conn: FramedConn<L::Stream>) -> DeviceHandle {
+        let (tx, rx) = mpsc::channel::<DeviceRequest>(QUEUE_SIZE);
+        let id = self.id.clone();
+        let device_status = Arc::clone(&self.device_status);
+        spawn(self.run(conn, rx));
+
+        DeviceHandle {
+            id,
+            tx,
+            device_status,
+        }
+    }
+
+    async fn run(self, conn: FramedConn<L::Stream>, mut rx: mpsc::Receiver<DeviceRequest>) {
+        let mut conn = Some(conn);
+        let mut backoff = INITIAL_BACKOFF;
+        // kept outside the loop so commands failed while disconnected do not restart the wait
+        let mut next_attempt = Instant::now();
+
+        // commands written to the device that are still waiting for a reply, keyed by seq
+        let mut pending: HashMap<u32, Responder> = HashMap::new();
+        let mut seq: u32 = 0;
+        let mut purge_timer = interval(PURGE_INTERVAL);
+
+        loop {
+            let mut dropped = None;
+
+            match conn.as_mut() {
+                Some(framed) => {
+                    select! {
+                        req = rx.recv() => {
+                            let mut req = match req {
+                                Some(req) => req,
+                                None => break,
+                            };
+
+                            // seq 0 is never used so a device echoing an unset field is not mistaken for a reply
+                            seq = seq.wrapping_add(1).max(1);
+                            req.packet.seq = seq;
+
+                            match framed.send(req.packet).await {
+                                Ok(()) if req.expect_reply => {
+                                    pending.insert(seq, req.resp);
+                                }
+                                Ok(()) => self.respond(req.resp, Ok(None)),
+                                Err(e) => {
+                                    dropped = Some(s!(e));
+                                    self.respond(req.resp, Err(DeviceError::Io(s!(e))));
+                                }
+                            }
+                        }
+                        // read every frame the device sends and match it to an outstanding command,
+                        // which also notices a connection closed by the device
+                        res = framed.recv_frame() => {
+                            match res {
+                                Ok(body) => {
+                                    if let Err(e) = self.received(body, &mut pending).await {
+                                        dropped = Some(e);
+                                    }
+                                }
+                                // after a corrupted frame the next length header cannot be trusted
+                                Err(e) => {
+                                    if let Some(e) = e.downcast_ref::<PodPacketError>() {
+                                        record_malformed(&self.device_status, &self.id, e).await;
+                                    }
+                                    dropped = Some(s!(e));
+                                }
+                            }
+                        }
+                        // forget commands whose deadline passed, a late reply is then counted as unmatched
+                        _ = purge_timer.tick() => pending.retain(|_, resp_tx| !resp_tx.is_closed()),
+                    }
+                }
+                None => {
+                    select! {
+                        req = rx.recv() => {
+                            match req {
+                                Some(req) => self.respond(req.resp, Err(DeviceError::Disconnected)),
+                                None => break,
+                            }
+                        }
+                        _ = sleep_until(next_attempt) => {
+                            match self.reconnect().await {
+                                Ok((framed, hs)) => {
+                                    conn = Some(framed);
+                                    backoff = INITIAL_BACKOFF;
+                                    self.reconnected(hs).await;
+                                }
+                                Err(e) => {
+                                    println!("pod_conn_svc: reconnect to device {} failed: {}", self.id, e);
+                                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                                    next_attempt = Instant::now() + backoff;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+            if let Some(e) = dropped {
+                println!("pod_conn_svc: lost connection to device {}: {}", self.id, e);
+                conn = None;
+                next_attempt = Instant::now() + backoff;
+                for (_, resp_tx) in pending.drain() {
+                    self.respond(resp_tx, Err(DeviceError::Disconnected));
+                }
+                self.device_status
+                    .lock()
+                    .await
+                    .entry(self.id.clone())
+                    .or_default()
+                    .conn_state = ConnState::Reconnecting;
+            }
+        }
+
+        println!("pod_conn_svc: connection to device {} closed", self.id);
+    }
+
+    /// Route a frame from the device to the command it answers
+    /// A reply that cannot be decoded fails its command straight away if the command can be told,
+    /// by the seq in its header when that is still readable, or as the only command in flight
+    /// Returns why the connection must be dropped, if the device reported a corrupted frame
+    async fn received(
+        &self,
+        body: Vec<u8>,
+        pending: &mut HashMap<u32, Responder>,
+    ) -> Result<(), String> {
+        let seq = peek_seq(&body);
+
+        match decode(body) {
+            Ok(pkt) if pkt.seq == 0 && pkt.cmd_type == CMD_CORRUPTED => {
+                let mut device_status = self.device_status.lock().await;
+                let status = device_status.entry(self.id.clone()).or_default();
+                status.rejected_frames += 1;
+                eprintln!(
+                    "pod_conn_svc: device {} received a corrupted frame ({} total)",
+                    self.id, status.rejected_frames
+                );
+
+                return Err(s!("device received a corrupted frame"));
+            }
+            Ok(pkt) if pkt.seq == 0 => self.unsolicited(pkt).await,
+            Ok(resp) => match pending.remove(&resp.seq) {
+                Some(resp_tx) => self.respond(resp_tx, Ok(Some(resp))),
+                None => self.unmatched(&resp).await,
+            },
+            Err(e) => {
+                record_malformed(&self.device_status, &self.id, &e).await;
+
+                let seq = match seq {
+                    Some(seq) => Some(seq),
+                    None if pending.len() == 1 => pending.keys().next().copied(),
+                    None => None,
+                };
+                if let Some(resp_tx) = seq.and_then(|seq| pending.remove(&seq)) {
+                    self.respond(resp_tx, Err(DeviceError::Malformed(s!(e))));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Count and report a frame whose seq does not match any outstanding command
+    /// These are stale replies to commands that already failed, or duplicates
+    async fn unmatched(&self, resp: &PodPacket) {
+        let mut device_status = self.device_status.lock().await;
+        let status = device_status.entry(self.id.clone()).or_default();
+        status.unmatched_replies += 1;
+
+        eprintln!(
+            "pod_conn_svc: unmatched reply from device {} (seq {}, cmd {}, {} total)",
+            self.id, resp.seq, resp.cmd_type, status.unmatched_replies
+        );
+    }
+
+    /// Pass a fault, warning or log message or pushed telemetry sent by the device to pod_conn_svc
+    /// Any other frame with seq 0 is counted as unmatched
+    async fn unsolicited(&self, pkt: PodPacket) {
+        let severity = Severity::from_cmd(pkt.cmd_type);
+        if severity.is_none() && pkt.cmd_type != CMD_TELEMETRY {
+            return self.unmatched(&pkt).await;
+        }
+        // stamped on arrival, since embedded devices may not have system time
+        let time_ms = unix_time_ms();
+
+        let payload = match decode_payload(pkt.payload) {
+            Ok(payload) => payload,
+            Err(e) => return record_malformed(&self.device_status, &self.id, &e).await,
+        };
+
+        let severity = match severity {
+            Some(severity) => severity,
+            None => {
+                let push = PushedTelemetry {
+                    device_id: self.id.clone(),
+                    payload,
+                    time_ms,
+                };
+                return match self.tx_push.try_send(push) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) => {
+                        record_dropped(&self.device_status, &self.id).await
+                    }
+                    Err(TrySendError::Closed(_)) => eprintln!("device->pod push failed"),
+                };
+            }
+        };
+
+        let event = DeviceEvent::Message(DeviceMessage {
+            device_id: self.id.clone(),
+            severity,
+            code: payload.target_cmd_code,
+            message: payload.message,
+            time: time_ms / 1000,
+        });
+        if let Err(e) = self.tx_event.send(event).await {
+            eprintln!("device->pod failed: {}", e);
+        }
+    }
+
+    /// Open a new connection to the device, authenticate it and repeat the handshake
+    async fn reconnect(&self) -> Result<(FramedConn<L::Stream>, Handshake), DeviceError> {
+        let res = match self.link.open().await {
+            Ok(stream) => {
+                let mut framed = FramedConn::new(stream, self.max_frame_size);
+                handshake(&mut framed).await.map(|hs| (framed, hs))
+            }
+            Err(e) => Err(e),
+        };
+
+        let conn_state = match res {
+            Err(DeviceError::Incompatible(_)) => ConnState::Incompatible,
+            Err(DeviceError::Unauthenticated(_)) => ConnState::Unauthenticated,
+            _ => return res,
+        };
+        self.device_status
+            .lock()
+            .await
+            .entry(self.id.clone())
+            .or_default()
+            .conn_state = conn_state;
+
+        res
+    }
+
+    /// Mark the device connected again and ask pod_conn_svc to re-run discovery
+    async fn reconnected(&self, hs: Handshake) {
+        println!(
+            "pod_conn_svc: reconnected to device {} (protocol version {}, firmware {})",
+            self.id, hs.version, hs.firmware
+        );
+
+        {
+            let mut status = self.device_status.lock().await;
+            let status = status.entry(self.id.clone()).or_default();
+            status.conn_state = ConnState::Connected;
+            status.protocol_version = Some(hs.version);
+            status.firmware = Some(hs.firmware);
+            status.reconnects += 1;
+        }
+
+        if let Err(e) = self
+            .tx_event
+            .send(DeviceEvent::Reconnected(self.id.clone()))
+            .await
+        {
+            eprintln!("device->pod failed: {}", e);
+        }
+    }
+
+    fn respond(&self, resp: Responder, res: Result<Option<PodPacket>, DeviceError>) {
+        if resp.send(res).is_err() {
+            eprintln!("pod_conn_svc: result for device {} dropped", self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pod_frame::DEFAULT_MAX_FRAME_SIZE;
+    use crate::pod_packet::CMD_FAULT;
+    use tokio::io::{duplex, DuplexStream};
+
+    /// Link whose streams are handed out by the test, one per connection
+    struct TestLink(Mutex<mpsc::Receiver<DuplexStream>>);
+
+    impl DeviceLink for TestLink {
+        type Stream = DuplexStream;
+
+        async fn open(&self) -> Result<DuplexStream, DeviceError> {
+            match self.0.lock().await.try_recv() {
+                Ok(stream) => Ok(stream),
+                Err(_) => Err(DeviceError::Io(s!("device unreachable"))),
+            }
+        }
+    }
+
+    struct Harness {
+        handle: DeviceHandle,
+        device: FramedConn<DuplexStream>,
+        device_status: Arc<Mutex<HashMap<String, DeviceStatus>>>,
+        rx_event: mpsc::Receiver<DeviceEvent>,
+        rx_push: mpsc::Receiver<PushedTelemetry>,
+        tx_stream: mpsc::Sender<DuplexStream>,
+    }
+
+    fn pipe() -> (DuplexStream, FramedConn<DuplexStream>) {
+        let (pod, device) = duplex(4096);
+        (pod, FramedConn::new(device, DEFAULT_MAX_FRAME_SIZE))
+    }
+
+    /// Spawn a connection task over an in-memory stream, the device end is driven by the test
+    fn start(push_queue: usize) -> Harness {
+        let (tx_stream, rx_stream) = mpsc::channel(4);
+        let (tx_event, rx_event) = mpsc::channel(8);
+        let (tx_push, rx_push) = mpsc::channel(push_queue);
+        let device_status = Arc::new(Mutex::new(HashMap::new()));
+
+        let conn = DeviceConn {
+            id: s!("dev"),
+            link: TestLink(Mutex::new(rx_stream)),
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            device_status: Arc::clone(&device_status),
+            tx_event,
+            tx_push,
+        };
+        let (pod, device) = pipe();
+
+        Harness {
+            handle: conn.spawn(FramedConn::new(pod, DEFAULT_MAX_FRAME_SIZE)),
+            device,
+            device_status,
+            rx_event,
+            rx_push,
+            tx_stream,
+        }
+    }
+
+    fn reply(req: &PodPacket) -> PodPacket {
+        let mut resp = PodPacket::new(req.cmd_type, encode_payload(PodPacketPayload::new()));
+        resp.seq = req.seq;
+        resp
+    }
+
+    fn unsolicited(cmd: u8, message: &str) -> PodPacket {
+        let mut payload = PodPacketPayload::new();
+        payload.message = s!(message);
+        PodPacket::new(cmd, encode_payload(payload))
+    }
+
+    async fn status(h: &Harness) -> DeviceStatus {
+        h.device_status
+            .lock()
+            .await
+            .get("dev")
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    const DEADLINE: Duration = Duration::from_secs(2);
+
+    #[tokio::test]
+    async fn matches_out_of_order_replies_by_seq() {
+        let mut h = start(8);
+
+        let (first, second) = (h.handle.clone(), h.handle.clone());
+        let first =
+            spawn(async move { first.request(10, PodPacketPayload::new(), DEADLINE).await });
+        let second =
+            spawn(async move { second.request(11, PodPacketPayload::new(), DEADLINE).await });
+
+        // both commands are in flight before either is answered, the later one is answered first
+        let a = h.device.recv().await.unwrap();
+        let b = h.device.recv().await.unwrap();
+        assert_ne!(a.seq, b.seq);
+        h.device.send(reply(&b)).await.unwrap();
+        h.device.send(reply(&a)).await.unwrap();
+
+        let first = first.await.unwrap().unwrap().unwrap();
+        let second = second.await.unwrap().unwrap().unwrap();
+        assert_eq!(first.cmd_type, 10);
+        assert_eq!(second.cmd_type, 11);
+        assert_eq!(status(&h).await.unmatched_replies, 0);
+    }
+
+    #[tokio::test]
+    async fn times_out_and_purges_unanswered_commands() {
+        let mut h = start(8);
+
+        let res = h
+            .handle
+            .request(10, PodPacketPayload::new(), Duration::from_millis(50))
+            .await;
+        assert!(matches!(res, Err(DeviceError::TimedOut(_))));
+        assert_eq!(status(&h).await.timeouts, 1);
+
+        // once the command is purged its late reply is counted as unmatched
+        let req = h.device.recv().await.unwrap();
+        tokio::time::sleep(PURGE_INTERVAL * 2).await;
+        h.device.send(reply(&req)).await.unwrap();
+
+        // the connection still works afterwards
+        let handle = h.handle.clone();
+        let next =
+            spawn(async move { handle.request(11, PodPacketPayload::new(), DEADLINE).await });
+        let req = h.device.recv().await.unwrap();
+        h.device.send(reply(&req)).await.unwrap();
+        assert_eq!(next.await.unwrap().unwrap().unwrap().cmd_type, 11);
+        assert_eq!(status(&h).await.unmatched_replies, 1);
+    }
+
+    #[tokio::test]
+    async fn routes_unsolicited_frames() {
+        let mut h = start(1);
+
+        h.device
+            .send(unsolicited(CMD_FAULT, "e-stop pressed"))
+            .await
+            .unwrap();
+        match h.rx_event.recv().await {
+            Some(DeviceEvent::Message(msg)) => {
+                assert_eq!(msg.device_id, "dev");
+                assert_eq!(msg.severity, Severity::Fault);
+                assert_eq!(msg.message, "e-stop pressed");
+            }
+            _ => panic!("expected a fault message"),
+        }
+
+        // pushed readings go to their own queue, and are dropped once it is full
+        for _ in 0..3 {
+            h.device.send(unsolicited(CMD_TELEMETRY, "")).await.unwrap();
+        }
+        let push = h.rx_push.recv().await.unwrap();
+        assert_eq!(push.device_id, "dev");
+        for _ in 0..50 {
+            if status(&h).await.dropped_samples == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(status(&h).await.dropped_samples, 2);
+
+        // any other command with seq 0 is not a reply to anything
+        h.device.send(unsolicited(10, "")).await.unwrap();
+        let handle = h.handle.clone();
+        let next =
+            spawn(async move { handle.request(10, PodPacketPayload::new(), DEADLINE).await });
+        let req = h.device.recv().await.unwrap();
+        h.device.send(reply(&req)).await.unwrap();
+        assert_eq!(next.await.unwrap().unwrap().unwrap().seq, req.seq);
+        assert_eq!(status(&h).await.unmatched_replies, 1);
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_the_device_drops() {
+        let mut h = start(8);
+
+        let handle = h.handle.clone();
+        let in_flight =
+            spawn(async move { handle.request(10, PodPacketPayload::new(), DEADLINE).await });
+        h.device.recv().await.unwrap();
+
+        // commands in flight fail as soon as the connection drops
+        let (pod, device) = pipe();
+        h.tx_stream.send(pod).await.unwrap();
+        h.device = device;
+        assert!(matches!(
+            in_flight.await.unwrap(),
+            Err(DeviceError::Disconnected)
+        ));
+
+        // the new connection repeats the handshake before it is used
+        let req = h.device.recv().await.unwrap();
+        assert_eq!(req.cmd_type, CMD_HANDSHAKE);
+        let mut hs = PodPacketPayload::new();
+        hs.firmware = s!("test-2.0");
+        let mut resp = PodPacket::new(CMD_HANDSHAKE, encode_payload(hs));
+        resp.seq = req.seq;
+        h.device.send(resp).await.unwrap();
+
+        assert!(matches!(
+            h.rx_event.recv().await,
+            Some(DeviceEvent::Reconnected(id)) if id == "dev"
+        ));
+        let status = status(&h).await;
+        assert_eq!(status.reconnects, 1);
+        assert_eq!(status.firmware.as_deref(), Some("test-2.0"));
+
+        let handle = h.handle.clone();
+        let next =
+            spawn(async move { handle.request(11, PodPacketPayload::new(), DEADLINE).await });
+        let req = h.device.recv().await.unwrap();
+        h.device.send(reply(&req)).await.unwrap();
+        assert_eq!(next.await.unwrap().unwrap().unwrap().cmd_type, 11);
+    }
+}
@@ -0,0 +1,1556 @@
+use crate::pod_frame::FramedConn;
+use crate::pod_packet::{
+    PodPacket, PodPacketError, CMD_FAULT, CMD_HEARTBEAT, CMD_LOG, CMD_SUBSCRIBE, CMD_TELEMETRY,
+    CMD_WARNING,
+};
+use crate::pod_packet_payload::{
+    decode_payload, encode_payload, CommandArg, FieldType, FieldValue, PodPacketPayload,
+};
+use shared::{
+    device::{Device, DeviceCommand, DeviceField},
+    telemetry::TelemetryData,
+};
+
+use anyhow::Result;
+use futures_util::future::join_all;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
+use tokio::{
+    spawn,
+    sync::{mpsc::error::TrySendError, mpsc::Receiver, mpsc::Sender, Mutex},
+    time::{self, Duration, Instant},
+};
+
+/// Default time between heartbeats sent to every connected device
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Default deadline for a device to answer a heartbeat
+pub const DEFAULT_HEARTBEAT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Highest rate a device may be asked to push telemetry at
+pub const MAX_PUSH_RATE_HZ: u16 = 1000;
+
+/// Pushed readings buffered on the way to tele_svc before further readings are dropped,
+/// one second of pushes from a device at MAX_PUSH_RATE_HZ
+pub const PUSH_QUEUE_SIZE: usize = MAX_PUSH_RATE_HZ as usize;
+
+/// Deadlines for a device to answer each class of command
+/// Every command sent to a device fails with DeviceError::TimedOut once its deadline passes,
+/// so no device can hold up pod_conn_svc for longer than this
+#[derive(Clone, Copy)]
+pub struct CommandTimeouts {
+    // kept tight, an emergency stop cannot wait on a device that stopped answering
+    pub brake: Duration,
+    pub launch: Duration,
+    pub discovery: Duration,
+    pub telemetry: Duration,
+    pub disconnect: Duration,
+    pub subscribe: Duration,
+    // device specific commands sent through link_svc
+    pub device_cmd: Duration,
+}
+
+impl Default for CommandTimeouts {
+    fn default() -> Self {
+        Self {
+            brake: Duration::from_millis(100),
+            launch: Duration::from_millis(500),
+            discovery: Duration::from_secs(2),
+            telemetry: Duration::from_millis(500),
+            disconnect: Duration::from_millis(500),
+            subscribe: Duration::from_millis(500),
+            device_cmd: Duration::from_secs(1),
+        }
+    }
+}
+
+impl CommandTimeouts {
+    /// Deadline for the given command code
+    pub fn for_cmd(&self, cmd: u8) -> Duration {
+        match cmd {
+            255 => self.brake,
+            254 => self.launch,
+            1 => self.discovery,
+            2 => self.disconnect,
+            CMD_TELEMETRY => self.telemetry,
+            CMD_SUBSCRIBE => self.subscribe,
+            _ => self.device_cmd,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum PodState {
+    Unlocked,
+    Locked,
+    Moving,
+    Braking,
+}
+
+/// State of the connection to a single device
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+pub enum ConnState {
+    #[default]
+    Disconnected,
+    Connected,
+    Reconnecting,
+    // the device failed the handshake or speaks an unsupported protocol version
+    Incompatible,
+    // the device failed authentication over a secured link
+    Unauthenticated,
+}
+
+/// Link health reported for each device, keyed by device id
+/// Shared with link_svc so the client can see it alongside the device list
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DeviceStatus {
+    pub conn_state: ConnState,
+    // reported by the device during the connect-time handshake
+    pub protocol_version: Option<u8>,
+    pub firmware: Option<String>,
+    pub reconnects: u32,
+    pub malformed_frames: u32,
+    pub corrupted_frames: u32,
+    // frames sent by the pod that the device reported as corrupted
+    pub rejected_frames: u32,
+    // pushed readings dropped because pod_conn_svc or tele_svc fell behind
+    pub dropped_samples: u32,
+    pub unmatched_replies: u32,
+    // commands, heartbeats included, that got no reply before their deadline
+    pub timeouts: u32,
+    // why the device's last discovery response was rejected, None once it passes
+    pub discovery_error: Option<String>,
+    // unsolicited messages received from the device
+    pub faults: u32,
+    pub warnings: u32,
+    pub last_fault: Option<String>,
+
+    // a critical device missing a heartbeat while Moving triggers emergency braking
+    pub critical: bool,
+    // set when the device reports a fault, or a critical device misses a heartbeat while Locked
+    // launching is refused while it is set, it is cleared on the next lock
+    pub faulted: bool,
+    pub missed_beats: u32,
+    pub latency_ms: Option<f32>,
+}
+
+impl Default for DeviceStatus {
+    fn default() -> Self {
+        Self {
+            conn_state: ConnState::Disconnected,
+            protocol_version: None,
+            firmware: None,
+            reconnects: 0,
+            malformed_frames: 0,
+            corrupted_frames: 0,
+            rejected_frames: 0,
+            dropped_samples: 0,
+            unmatched_replies: 0,
+            timeouts: 0,
+            discovery_error: None,
+            faults: 0,
+            warnings: 0,
+            last_fault: None,
+            // devices are only critical once an operator marks them,
+            // so an unimportant sensor dropping out cannot brake the pod
+            critical: false,
+            faulted: false,
+            missed_beats: 0,
+            latency_ms: None,
+        }
+    }
+}
+
+/// Telemetry field declared by a device during discovery
+#[derive(Clone)]
+pub struct FieldSchema {
+    pub name: String,
+    pub field_type: FieldType,
+}
+
+/// Device specific command declared by a device during discovery
+/// Shared with link_svc so the client can see which arguments each command takes
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CommandSchema {
+    pub name: String,
+    pub code: u8,
+    pub args: Vec<CommandArg>,
+}
+
+impl CommandSchema {
+    /// Check the arguments supplied for this command against its declaration
+    /// and convert each one to its declared type
+    pub fn check_args(&self, args: &[FieldValue]) -> Result<Vec<FieldValue>, String> {
+        if args.len() != self.args.len() {
+            return Err(format!(
+                "command {} takes {} argument(s), received {}",
+                self.name,
+                self.args.len(),
+                args.len()
+            ));
+        }
+
+        self.args
+            .iter()
+            .zip(args)
+            .map(|(arg, value)| arg.coerce(value))
+            .collect()
+    }
+}
+
+/// Outcome of a device specific command, returned to the client through link_svc
+///
+/// A device accepts a command by replying with the same cmd_type, optionally returning values
+/// in field_names and telemetry_data.
+/// It rejects a command by replying with cmd_type 0 and its own error code in target_cmd_code.
+#[derive(Serialize, Deserialize)]
+pub struct CommandResult {
+    pub device_id: String,
+    pub cmd_code: u8,
+    pub ok: bool,
+    // the device did not reply before the command's deadline
+    pub timed_out: bool,
+    // set when the device itself rejected the command
+    pub error_code: Option<u8>,
+    pub error: Option<String>,
+    // values returned by the device, in the order it sent them
+    pub values: Vec<(String, FieldValue)>,
+    // round trip time to the device, None if the command was never sent
+    pub elapsed_ms: Option<f32>,
+}
+
+impl CommandResult {
+    fn failed(device_id: String, cmd_code: u8, error: String) -> Self {
+        Self {
+            device_id,
+            cmd_code,
+            ok: false,
+            timed_out: false,
+            error_code: None,
+            error: Some(error),
+            values: Vec::new(),
+            elapsed_ms: None,
+        }
+    }
+}
+
+/// Severity of a message a device sends without being asked
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Severity {
+    Log,
+    Warning,
+    Fault,
+}
+
+impl Severity {
+    /// Severity carried by an unsolicited packet's cmd_type, None for any other command
+    pub fn from_cmd(cmd: u8) -> Option<Self> {
+        match cmd {
+            CMD_LOG => Some(Severity::Log),
+            CMD_WARNING => Some(Severity::Warning),
+            CMD_FAULT => Some(Severity::Fault),
+            _ => None,
+        }
+    }
+}
+
+/// Fault, warning or log message sent by a device on its own, e.g. when a local e-stop is pressed
+/// Faults are escalated to emerg_svc, and every message is passed to tele_svc,
+/// which keeps the latest for the client and has database_svc store them
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DeviceMessage {
+    pub device_id: String,
+    pub severity: Severity,
+    // device specific code, e.g. which sensor or button raised the message
+    pub code: u8,
+    pub message: String,
+    // unix time in seconds the pod received the message
+    pub time: u64,
+}
+
+/// Telemetry fields a device pushes on its own at a fixed rate instead of being polled,
+/// stored per device id and sent to the device every time the pod is locked
+/// Used for high-rate channels like accelerometers that one second polling cannot keep up with
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Subscription {
+    pub fields: Vec<String>,
+    pub rate_hz: u16,
+}
+
+/// Single pushed telemetry reading, timestamped by the pod when it arrives
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TelemetrySample {
+    pub device_id: String,
+    pub field: String,
+    pub value: FieldValue,
+    // unix time in milliseconds the pod received the reading
+    pub time_ms: u64,
+}
+
+pub mod announce;
+pub mod device_conn;
+pub mod secure_link;
+
+use announce::DeviceCandidate;
+use device_conn::{
+    handshake, DeviceConn, DeviceError, DeviceEvent, DeviceHandle, DeviceLink, PushedTelemetry,
+};
+use secure_link::{DeviceStream, LinkSecurity, SecureLink};
+
+pub struct PodConnSvc {
+    pub conn_list: Vec<DeviceHandle>,
+    pub max_frame_size: usize,
+    pub heartbeat_interval: Duration,
+    pub heartbeat_timeout: Duration,
+    pub cmd_timeouts: CommandTimeouts,
+    // UDP port device announcements are received on
+    pub announce_port: u16,
+    // typed telemetry fields advertised by each device, keyed by device id
+    pub device_fields: HashMap<String, Vec<FieldSchema>>,
+
+    pub device_list: Arc<Mutex<Vec<Device>>>,
+    pub pod_state: Arc<Mutex<PodState>>,
+    pub device_status: Arc<Mutex<HashMap<String, DeviceStatus>>>,
+    // device specific commands advertised by each device, keyed by device id
+    pub device_commands: Arc<Mutex<HashMap<String, Vec<CommandSchema>>>>,
+    // how the link to each device is secured, keyed by device id, plain TCP if absent
+    pub device_security: Arc<Mutex<HashMap<String, LinkSecurity>>>,
+    // devices that announced themselves but have not been adopted yet, keyed by device id
+    pub device_candidates: Arc<Mutex<HashMap<String, DeviceCandidate>>>,
+    // telemetry each device pushes instead of being polled for, keyed by device id
+    pub device_subscriptions: Arc<Mutex<HashMap<String, Subscription>>>,
+
+    pub rx_device: Receiver<DeviceEvent>,
+    pub tx_device: Sender<DeviceEvent>,
+    pub rx_push: Receiver<PushedTelemetry>,
+    pub tx_push: Sender<PushedTelemetry>,
+    pub rx_ctrl: Receiver<PodPacket>,
+    pub tx_ctrl: Sender<PodPacket>,
+    pub rx_emerg: Receiver<u8>,
+    pub tx_emerg: Sender<u8>,
+    pub tx_fault: Sender<String>,
+    pub rx_link: Receiver<PodPacket>,
+    pub tx_link: Sender<PodPacket>,
+    pub rx_tele: Receiver<u8>,
+    pub tx_tele: Sender<Vec<TelemetryData>>,
+    pub tx_tele_msg: Sender<DeviceMessage>,
+    pub tx_tele_push: Sender<Vec<TelemetrySample>>,
+    pub rx_trip: Receiver<u8>,
+}
+
+/// pod_conn_svc opens and manages tcp streams to all embedded devices
+/// each stream is owned by its own device connection task
+/// sends all embedded commands, broadcasting to every device concurrently
+/// receives responses from embedded devices
+impl PodConnSvc {
+    pub async fn run(mut self) {
+        println!("pod_conn_svc: service running");
+
+        // record devices announcing themselves on the pod network as candidates for link_svc
+        spawn(announce::listen(
+            self.announce_port,
+            self.max_frame_size,
+            Arc::clone(&self.device_candidates),
+        ));
+
+        // repeating interval to check that every connected device is still answering
+        let mut heartbeat_timer = time::interval(self.heartbeat_interval);
+
+        loop {
+            tokio::select! {
+                //handle commands from ctrl_svc
+                //ctrl_cmd = self.rx_ctrl.recv() => {
+                //    self.send_cmd(0,ctrl_cmd.unwrap())
+                //},
+
+                _ = self.rx_emerg.recv() => {
+                    // check pod_state
+                    let moving = matches!(*self.pod_state.lock().await, PodState::Moving);
+
+                    if moving {
+                        // initiate braking
+                        if !self.engage_brakes().await {
+                            eprintln!("pod_conn_svc: emergency brake not acknowledged by every device");
+                        }
+                        *self.pod_state.lock().await = PodState::Braking;
+
+                        // braking command sent, return success to emerg_svc
+                        if let Err(e) = self.tx_emerg.send(1).await {
+                            eprintln!("pod->emerg failed: {}", e);
+                        };
+                    } else {
+                        // braking command unnecessary, return fail message to emerg_svc
+                        if let Err(e) = self.tx_emerg.send(0).await {
+                            eprintln!("pod->emerg failed: {}", e);
+                        };
+                    }
+                }
+
+                _ = heartbeat_timer.tick() => self.send_heartbeats(),
+
+                //handle commands from ctrl_svc
+                packet = self.rx_ctrl.recv() =>{
+
+                    let mut pkt = packet.unwrap().clone();
+                    let link_cmd = pkt.cmd_type;
+                    let payload = decode_payload(pkt.payload).unwrap_or_else(|_| PodPacketPayload::new());
+
+                    //parse the command, and act based on it's command type
+                    match link_cmd{
+                        //cmd to engage brakes
+                        255=>{
+                            let ok = self.engage_brakes().await;
+
+                            //send a single aggregated ACK back to ctrl_svc
+                            if let Err(e) = self.tx_ctrl.send(ack(255, ok)).await {
+                                eprintln!("pod->ctrl failed: {}", e);
+                            }
+                        }
+                        //cmd to launch pod
+                        254=>{
+                            //a device that faulted since the pod was locked must be cleared by locking again
+                            let faulted = self.faulted_devices().await;
+                            if !faulted.is_empty() {
+                                let mut payload = PodPacketPayload::new();
+                                payload.message = format!("Launch refused, devices faulted since the pod was locked: {}", faulted.join(", "));
+                                eprintln!("pod_conn_svc: {}", payload.message);
+                                if let Err(e) = self.tx_ctrl.send(PodPacket::new(0, encode_payload(payload))).await {
+                                    eprintln!("pod->ctrl failed: {}", e);
+                                }
+                                continue;
+                            }
+
+                            let ok = self.launch().await;
+
+                            //devices that acknowledged may already be launching, so every device is braked
+                            //the pod is left Moving unless every device acknowledged the brakes,
+                            //so ctrl_svc and emerg_svc can still brake it
+                            if !ok {
+                                eprintln!("pod_conn_svc: launch not acknowledged by every device, braking");
+                                if !self.engage_brakes().await {
+                                    eprintln!("pod_conn_svc: brakes not acknowledged after failed launch, pod is Moving");
+                                    *self.pod_state.lock().await = PodState::Moving;
+                                }
+                            }
+
+                            //send a single aggregated ACK back to ctrl_svc
+                            if let Err(e) = self.tx_ctrl.send(ack(254, ok)).await {
+                                eprintln!("pod->ctrl failed: {}", e);
+                            }
+                        }
+                        //cmd to activate device specific command
+                        _=>{
+
+                        }
+                    }
+
+                }
+
+                //handle commands from link_svc
+                packet = self.rx_link.recv() => {
+
+                    let mut pkt = packet.unwrap().clone();
+                    let link_cmd = pkt.cmd_type;
+                    let payload = decode_payload(pkt.payload).unwrap_or_else(|_| PodPacketPayload::new());
+
+                    //parse the command, and act based on it's command type
+                    match link_cmd{
+
+                        // lock cmd
+                        // start up all tcp connections
+                        // if successful, set pod state to locked state and trigger discovery packet
+                        //
+                        // if unsuccessful report unchanged state to user?
+                        1 => {
+
+                            //check if the pod is already locked before following through with the lock command
+                            //the state guard is dropped before replying, so link_svc is never awaited while holding it
+                            let unlocked = matches!(*self.pod_state.lock().await, PodState::Unlocked);
+
+                            if !unlocked {
+                                // locking command unnecessary, return fail message
+                                eprintln!("Pod already locked");
+                                pkt.payload = vec![0];
+                                pkt.cmd_type = 0;
+                                if let Err(e) = self.tx_link.send(pkt.clone()).await {
+                                    eprintln!("pod->link failed: {}", e);
+                                }
+                            }
+
+                            if unlocked {
+
+                                //open TCP connections to all devices
+                                //then send the discovery packet command to every device at once
+                                let res = match self.populate_conn_list().await {
+                                    Ok(()) => self.discover().await,
+                                    Err(errors) => Err(errors),
+                                };
+
+                                match res {
+                                    Ok(()) => {
+                                        //once successful, send a response to link_svc
+                                        pkt.payload = vec![0];
+                                        self.tx_link.send(pkt).await;
+                                    },
+                                    Err(errors) => {
+                                        //if unsuccessful, send the reason for each failed device to link_svc
+                                        pkt.payload = serde_json::to_vec(&errors).unwrap_or_default();
+                                        pkt.cmd_type = 0;
+                                        self.tx_link.send(pkt).await;
+                                    },
+                                };
+                            }
+
+
+                        },
+                        // unlock cmd
+                        // end all tcp connections
+                        // if successful, set pod state to unlocked state
+                        2 =>{
+                            let mut unlocked =true;
+
+                            //check if the pod is already locked before following through with the unlock command
+                            match *self.pod_state.lock().await {
+                                PodState::Locked => {
+                                    unlocked = false;
+                                },
+                                PodState::Unlocked => {
+                                    // locking command unnecessary, return fail message
+                                    eprintln!("Pod already unlocked");
+
+                                }
+                                _ => {
+                                    // unlocking command denied when pod is Moving or Braking
+                                    //return fail message
+                                    eprintln!("Pod may only be Unlocked when in Locked state");
+
+                                }
+                            }
+
+                            //if pod is in Locked state
+                            //follow through with setting it to Unlocked state
+                            if !unlocked {
+                                *self.pod_state.lock().await = PodState::Unlocked;
+
+                                //close TCP connections to all devices
+                                match self.clear_conn_list().await{
+                                    Ok(()) =>{
+                                        println!("device connections closed successfully");
+                                        //once successful, send a response to link_svc
+                                        pkt.payload = vec![0];
+                                        self.tx_link.send(pkt).await;
+                                    }
+                                    Err(())=>{
+                                        println!("error: could not close device connections");
+                                        //if unsuccessful, send a response to link_svc
+                                        pkt.payload = vec![0];
+                                        pkt.cmd_type = 0;
+                                        self.tx_link.send(pkt).await;
+                                    }
+                                };
+
+                            }
+                            else{
+                                //if unsuccessful, send a response to link_svc
+                                pkt.payload = vec![0];
+                                pkt.cmd_type = 0;
+                                self.tx_link.send(pkt).await;
+                            }
+
+                        }
+                        //send cmd to device
+                        3=>{
+                            //check that the pod is locked before sending the command
+                            let locked = matches!(*self.pod_state.lock().await, PodState::Locked);
+
+                            if !locked {
+                                eprintln!("Please lock the pod first");
+                            }
+
+                            //the device is waited on in its own task so the loop stays responsive to emerg_svc
+                            let handle = self.conn_list.iter().find(|h| h.id == payload.target_id).cloned();
+                            let device_commands = Arc::clone(&self.device_commands);
+                            let device_status = Arc::clone(&self.device_status);
+                            let deadline = self.cmd_timeouts.device_cmd;
+                            let tx_link = self.tx_link.clone();
+
+                            spawn(async move {
+                                let result = if locked {
+                                    device_cmd(handle, &device_commands, &device_status, deadline, payload).await
+                                } else {
+                                    CommandResult::failed(payload.target_id, payload.target_cmd_code, s!("Pod must be locked first"))
+                                };
+
+                                //the result is returned to link_svc as JSON and forwarded to the client unchanged
+                                let cmd_type = if result.ok { 3 } else { 0 };
+                                let resp = PodPacket::new(cmd_type, serde_json::to_vec(&result).unwrap_or_default());
+                                if let Err(e) = tx_link.send(resp).await {
+                                    eprintln!("pod->link failed: {}", e);
+                                }
+                            });
+                        }
+                        _ => ()
+                    }
+                },
+                //handle telemetry requests from tele_svc
+                Some(_) = self.rx_tele.recv() => self.get_telemetry(),
+                _ = self.rx_trip.recv() => {
+                    self.engage_brakes().await;
+                }
+
+                //handle notifications from device connection tasks
+                Some(event) = self.rx_device.recv() => {
+                    match event {
+                        //re-run discovery on a device whose connection was re-established
+                        DeviceEvent::Reconnected(id) => self.rediscover(id),
+                        DeviceEvent::Rediscovered(id, res) => {
+                            if let Some(index) = self.conn_list.iter().position(|h| h.id == id) {
+                                match self.handle_response(index, 1, res).await {
+                                    //the device forgot its subscription along with the old connection
+                                    Ok(()) => self.resubscribe(id),
+                                    Err(e) => println!("pod_conn_svc: rediscovery of device {} failed: {}", id, e),
+                                }
+                            }
+                        }
+                        DeviceEvent::Heartbeat(id, latency) => self.heartbeat_ok(id, latency).await,
+                        DeviceEvent::HeartbeatMissed(id) => self.heartbeat_missed(id).await,
+                        DeviceEvent::Message(msg) => self.device_message(msg).await,
+                    }
+                }
+                //handle telemetry pushed by subscribed devices
+                Some(push) = self.rx_push.recv() => self.pushed_telemetry(push).await,
+            }
+        }
+    }
+
+    /// Query every connected device for the fields it advertised during discovery
+    /// and return the combined readings to tele_svc
+    /// Fields the device pushes through a subscription are not polled
+    /// Runs in its own task so a slow device never holds up the service loop
+    fn get_telemetry(&self) {
+        let handles = self.conn_list.clone();
+        let device_fields = self.device_fields.clone();
+        let device_status = Arc::clone(&self.device_status);
+        let device_subscriptions = Arc::clone(&self.device_subscriptions);
+        let deadline = self.cmd_timeouts.telemetry;
+        let tx_tele = self.tx_tele.clone();
+
+        spawn(async move {
+            let subscriptions = device_subscriptions.lock().await.clone();
+            let requests = handles.iter().filter_map(|handle| {
+                let mut fields = device_fields.get(&handle.id)?.clone();
+                if let Some(sub) = subscriptions.get(&handle.id) {
+                    fields.retain(|f| !sub.fields.contains(&f.name));
+                }
+                if fields.is_empty() {
+                    return None;
+                }
+
+                let mut payload = PodPacketPayload::new();
+                payload.target_id = handle.id.clone();
+                payload.field_names = fields.iter().map(|f| f.name.clone()).collect();
+
+                Some(async move {
+                    let res = handle.request(CMD_TELEMETRY, payload, deadline).await;
+                    (handle.id.clone(), fields, res)
+                })
+            });
+
+            let mut tele_data = Vec::new();
+            for (id, fields, res) in join_all(requests).await {
+                match res {
+                    Ok(Some(resp)) => match read_telemetry(resp, &fields) {
+                        Ok(mut data) => tele_data.append(&mut data),
+                        Err(e) => record_malformed(&device_status, &id, &e).await,
+                    },
+                    Ok(None) => (),
+                    Err(e) => {
+                        eprintln!("pod_conn_svc: telemetry from device {} failed: {}", id, e)
+                    }
+                }
+            }
+
+            if let Err(e) = tx_tele.send(tele_data).await {
+                eprintln!("pod->tele failed: {}", e);
+            }
+        });
+    }
+
+    /// Returns the reason for each device that could not be connected
+    async fn populate_conn_list(&mut self) -> Result<(), HashMap<String, String>> {
+        if !self.conn_list.is_empty() {
+            self.conn_list.clear()
+        }
+
+        // connect to every device in devicelist at once, authenticating devices with secured links,
+        // and hand each connection to its own connection task
+        let devices = self.device_list.lock().await.clone();
+        let results = join_all(devices.into_iter().map(|dev| self.open_conn(dev))).await;
+
+        let mut errors = HashMap::new();
+        for res in results {
+            match res {
+                Ok((conn, framed)) => self.conn_list.push(conn.spawn(framed)),
+                Err((id, e)) => {
+                    errors.insert(id, e);
+                }
+            }
+        }
+
+        // if devicelist.length == conn_list.length then all devices are connected and pod_state is locked
+        // otherwise drop the partial list, closing any connections that were opened
+        if self.device_list.lock().await.len() != self.conn_list.len() {
+            for handle in self.conn_list.clone() {
+                self.set_conn_state(&handle.id, ConnState::Disconnected)
+                    .await;
+            }
+            self.conn_list.clear();
+            return Err(errors);
+        }
+        *self.pod_state.lock().await = PodState::Locked;
+
+        Ok(())
+    }
+
+    /// Connect to a single device and run the handshake
+    /// Returns the connection ready to be spawned, or the device id and the reason it was refused
+    async fn open_conn(
+        &self,
+        dev: Device,
+    ) -> Result<(DeviceConn<SecureLink>, FramedConn<DeviceStream>), (String, String)> {
+        let security = match self.device_security.lock().await.get(&dev.id) {
+            Some(security) => security.clone(),
+            None => {
+                eprintln!(
+                    "pod_conn_svc: WARNING no link security set for device {}, connecting over unauthenticated plain TCP",
+                    dev.id
+                );
+                LinkSecurity::Plain
+            }
+        };
+
+        self.device_status
+            .lock()
+            .await
+            .entry(dev.id.clone())
+            .or_default()
+            .faulted = false;
+
+        let link = SecureLink {
+            addr: format!("{}:{}", dev.ip_address, dev.port),
+            security,
+        };
+        let stream = match link.open().await {
+            Ok(s) => s,
+            // refuse devices that fail authentication
+            Err(e @ DeviceError::Unauthenticated(_)) => {
+                println!("pod_conn_svc: device {} refused: {}", dev.id, e);
+                self.set_conn_state(&dev.id, ConnState::Unauthenticated)
+                    .await;
+                return Err((dev.id, s!(e)));
+            }
+            Err(e) => {
+                println!("couldn't connect ");
+                self.set_conn_state(&dev.id, ConnState::Disconnected).await;
+                return Err((dev.id, format!("could not connect: {}", e)));
+            }
+        };
+
+        // refuse devices that fail the handshake or speak an unsupported protocol version
+        let mut framed = FramedConn::new(stream, self.max_frame_size);
+        let hs = match handshake(&mut framed).await {
+            Ok(hs) => hs,
+            Err(e) => {
+                println!("pod_conn_svc: device {} refused: {}", dev.id, e);
+                self.set_conn_state(&dev.id, ConnState::Incompatible).await;
+                return Err((dev.id, s!(e)));
+            }
+        };
+        println!(
+            "pod_conn_svc: device {} speaks protocol version {}, firmware {}",
+            dev.id, hs.version, hs.firmware
+        );
+
+        {
+            let mut device_status = self.device_status.lock().await;
+            let status = device_status.entry(dev.id.clone()).or_default();
+            status.conn_state = ConnState::Connected;
+            status.protocol_version = Some(hs.version);
+            status.firmware = Some(hs.firmware);
+        }
+
+        let conn = DeviceConn {
+            id: dev.id,
+            link,
+            max_frame_size: self.max_frame_size,
+            device_status: Arc::clone(&self.device_status),
+            tx_event: self.tx_device.clone(),
+            tx_push: self.tx_push.clone(),
+        };
+        Ok((conn, framed))
+    }
+
+    /// Run discovery on every connected device, then start every telemetry subscription
+    /// A device that fails either blocks locking, so every connection is closed again
+    /// and the reason for each failed device is returned
+    async fn discover(&mut self) -> Result<(), HashMap<String, String>> {
+        let mut errors: HashMap<String, String> = self
+            .broadcast_cmd(1)
+            .await
+            .into_iter()
+            .zip(self.conn_list.iter())
+            .filter_map(|(res, handle)| res.err().map(|e| (handle.id.clone(), e)))
+            .collect();
+
+        if errors.is_empty() {
+            errors = self.subscribe_all().await;
+        }
+
+        if errors.is_empty() {
+            return Ok(());
+        }
+
+        eprintln!(
+            "pod_conn_svc: discovery failed on {} device(s), lock refused",
+            errors.len()
+        );
+        if let Err(()) = self.clear_conn_list().await {
+            println!("error: could not close device connections");
+        }
+        *self.pod_state.lock().await = PodState::Unlocked;
+
+        Err(errors)
+    }
+
+    /// Send every device with a subscription its fields and rate, all at once
+    /// Returns the reason for each device that could not be subscribed
+    async fn subscribe_all(&self) -> HashMap<String, String> {
+        let subscriptions = self.device_subscriptions.lock().await.clone();
+        let deadline = self.cmd_timeouts.subscribe;
+        let requests = self.conn_list.iter().filter_map(|handle| {
+            let sub = subscriptions.get(&handle.id)?;
+            let fields = self.device_fields.get(&handle.id)?;
+
+            Some(async move {
+                let res = subscribe(handle, fields, sub, deadline).await;
+                (handle.id.clone(), res)
+            })
+        });
+
+        join_all(requests)
+            .await
+            .into_iter()
+            .filter_map(|(id, res)| res.err().map(|e| (id, e)))
+            .collect()
+    }
+
+    /// Repeat a device's subscription in its own task after it reconnected
+    fn resubscribe(&self, id: String) {
+        let handle = match self.conn_list.iter().find(|h| h.id == id) {
+            Some(handle) => handle.clone(),
+            None => return,
+        };
+        let fields = self.device_fields.get(&id).cloned().unwrap_or_default();
+        let device_subscriptions = Arc::clone(&self.device_subscriptions);
+        let deadline = self.cmd_timeouts.subscribe;
+
+        spawn(async move {
+            let sub = match device_subscriptions.lock().await.get(&id) {
+                Some(sub) => sub.clone(),
+                None => return,
+            };
+            if let Err(e) = subscribe(&handle, &fields, &sub, deadline).await {
+                eprintln!("pod_conn_svc: resubscribing device {} failed: {}", id, e);
+            }
+        });
+    }
+
+    /// Check readings pushed by a subscribed device against the fields it declared during discovery
+    /// and pass them to tele_svc
+    /// Readings are dropped instead of waited on if tele_svc falls behind,
+    /// so the service loop stays free to brake the pod
+    async fn pushed_telemetry(&mut self, push: PushedTelemetry) {
+        let id = push.device_id;
+        let fields = match self.device_fields.get(&id) {
+            Some(fields) => fields,
+            None => return,
+        };
+
+        match read_pushed(&id, push.payload, fields, push.time_ms) {
+            Ok(samples) => match self.tx_tele_push.try_send(samples) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => record_dropped(&self.device_status, &id).await,
+                Err(TrySendError::Closed(_)) => eprintln!("pod->tele push failed"),
+            },
+            Err(e) => record_malformed(&self.device_status, &id, &e).await,
+        }
+    }
+
+    async fn clear_conn_list(&mut self) -> Result<(), ()> {
+        //tell every device to disconnect
+        let failed = self
+            .broadcast_cmd(2)
+            .await
+            .iter()
+            .filter(|res| res.is_err())
+            .count();
+        if failed > 0 {
+            println!("pod_conn_svc: disconnect failed on {} device(s)", failed);
+        }
+
+        //dropping the handles ends each connection task and closes its stream
+        for handle in self.conn_list.clone() {
+            self.set_conn_state(&handle.id, ConnState::Disconnected)
+                .await;
+        }
+        self.conn_list.clear();
+
+        Ok(())
+    }
+
+    /// Send the braking command to every device at once
+    /// Returns true if every device acknowledged
+    async fn engage_brakes(&mut self) -> bool {
+        self.broadcast_cmd(255).await.iter().all(|res| res.is_ok())
+    }
+
+    /// Send the launch command to every device at once
+    /// Returns true if every device acknowledged
+    async fn launch(&mut self) -> bool {
+        self.broadcast_cmd(254).await.iter().all(|res| res.is_ok())
+    }
+
+    /// Send the same command to every connected device concurrently,
+    /// then process each response once the slowest device has answered or its deadline passed
+    async fn broadcast_cmd(&mut self, cmd: u8) -> Vec<Result<(), String>> {
+        let handles = self.conn_list.clone();
+        let deadline = self.cmd_timeouts.for_cmd(cmd);
+        let results = join_all(
+            handles
+                .iter()
+                .map(|handle| handle.request(cmd, PodPacketPayload::new(), deadline)),
+        )
+        .await;
+
+        let mut out = Vec::new();
+        for (index, res) in results.into_iter().enumerate() {
+            out.push(self.handle_response(index, cmd, res).await);
+        }
+
+        out
+    }
+
+    /// Re-run discovery on a single device in its own task
+    /// The result comes back as DeviceEvent::Rediscovered so the loop never waits on the device
+    fn rediscover(&self, id: String) {
+        let handle = match self.conn_list.iter().find(|h| h.id == id) {
+            Some(handle) => handle.clone(),
+            None => return,
+        };
+        let deadline = self.cmd_timeouts.discovery;
+        let tx_device = self.tx_device.clone();
+
+        spawn(async move {
+            let res = handle.request(1, PodPacketPayload::new(), deadline).await;
+            if let Err(e) = tx_device.send(DeviceEvent::Rediscovered(id, res)).await {
+                eprintln!("rediscovery->pod failed: {}", e);
+            }
+        });
+    }
+
+    /// Process the result of a command sent to the device at index
+    /// Returns the reason the command failed
+    async fn handle_response(
+        &mut self,
+        index: usize,
+        cmd: u8,
+        res: Result<Option<PodPacket>, DeviceError>,
+    ) -> Result<(), String> {
+        let resp = match res {
+            Ok(Some(resp)) => resp,
+            //no response expected
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                println!("failed to send command {}: {}", cmd, e);
+                return Err(s!(e));
+            }
+        };
+
+        //the reply was matched to this command by seq, so it must also echo the command type
+        if resp.cmd_type != cmd {
+            println!(
+                "pod_conn_svc: device {} answered cmd {} with cmd {}",
+                self.conn_list[index].id, cmd, resp.cmd_type
+            );
+            return Err(format!("answered cmd {} with cmd {}", cmd, resp.cmd_type));
+        }
+
+        //decode the response to the command
+        let payload = match decode_payload(resp.payload) {
+            Ok(payload) => payload,
+            Err(e) => {
+                record_malformed(&self.device_status, &self.conn_list[index].id, &e).await;
+                return Err(s!(e));
+            }
+        };
+
+        //process the response, based on the type of command that it is responding to
+        match cmd {
+            //response to an emergency/braking command
+            255 => {
+                println!("pod_conn: Braking Sequence successful");
+            }
+            254 => {
+                println!("pod_conn: Launching Sequence successful");
+            }
+            //error response
+            0 => {}
+            //response to a discovery command
+            1 => {
+                //reject responses that would leave the device half described
+                //or let it take over a command code the pod uses itself
+                let id = self.conn_list[index].id.clone();
+                let discovery_error = payload.check_discovery().err();
+                self.device_status
+                    .lock()
+                    .await
+                    .entry(id.clone())
+                    .or_default()
+                    .discovery_error = discovery_error.clone();
+                if let Some(e) = discovery_error {
+                    eprintln!("pod_conn_svc: device {} failed discovery: {}", id, e);
+                    return Err(format!("discovery rejected: {}", e));
+                }
+
+                //extract the list of new field names along with their declared types
+                let mut field_list = Vec::<DeviceField>::new();
+                let mut field_schema = Vec::<FieldSchema>::new();
+                for (field, field_type) in payload.field_names.into_iter().zip(payload.field_types)
+                {
+                    field_list.push(DeviceField::new(field.clone()));
+                    field_schema.push(FieldSchema {
+                        name: field,
+                        field_type,
+                    });
+                }
+
+                //extract the list of new command anmes and their corresponding codes
+                //along with the arguments each command takes
+                let mut cmd_list = Vec::<DeviceCommand>::new();
+                let mut cmd_schema = Vec::<CommandSchema>::new();
+                for index in 0..payload.command_codes.len() {
+                    cmd_list.push(DeviceCommand::new(
+                        payload.command_names[index].clone(),
+                        payload.command_codes[index],
+                    ));
+                    cmd_schema.push(CommandSchema {
+                        name: payload.command_names[index].clone(),
+                        code: payload.command_codes[index],
+                        args: payload.command_args[index].clone(),
+                    });
+                }
+
+                // clone the target device from the shared device list
+                let mut new_device = self.device_list.lock().await[index].clone();
+
+                // make changes to an updated clone of the original Device instance
+                new_device.fields = field_list;
+                new_device.commands = cmd_list;
+
+                // DEBUGGING PURPOSES
+                println!("Device index: {}", index);
+                // print the new fields/commands
+                println!("Discovered Telemetry Fields:");
+                for field in field_schema.iter() {
+                    println!("{} ({:?})", field.name, field.field_type);
+                }
+
+                println!("Discovered Commands:");
+                for cmd in cmd_schema.iter() {
+                    let args: Vec<String> = cmd
+                        .args
+                        .iter()
+                        .map(|arg| format!("{}: {:?}", arg.name, arg.arg_type))
+                        .collect();
+                    println!("{} {} ({})", cmd.code, cmd.name, args.join(", "));
+                }
+
+                println!("--------------------");
+
+                // overwrite the original device in the list
+                // with the updated clone
+                self.device_fields
+                    .insert(new_device.id.clone(), field_schema);
+                self.device_commands
+                    .lock()
+                    .await
+                    .insert(new_device.id.clone(), cmd_schema);
+                self.device_list.lock().await[index] = new_device;
+
+                // success message
+                println!("Discovered Fields and Commands saved");
+            }
+            //command #2 is reserved for disconnect commands
+            //should not receive a response
+            2 => {
+                println!("Error: received response to disconnect command");
+            }
+            //device specific commands (DEVICE_CMDS) are answered through device_cmd
+            //and the CMD_ protocol commands by the requests that sent them,
+            //so only 1, 2, 254 and 255 are ever broadcast
+            3..=253 => {
+                println!("pod_conn_svc: unexpected broadcast response to cmd {}", cmd);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Send a heartbeat to every connected device without blocking the service loop
+    /// Each result comes back as a DeviceEvent once the device answers or the deadline passes
+    fn send_heartbeats(&self) {
+        for handle in self.conn_list.clone() {
+            let deadline = self.heartbeat_timeout;
+            let tx_device = self.tx_device.clone();
+
+            spawn(async move {
+                let start = Instant::now();
+                let event = match handle
+                    .request(CMD_HEARTBEAT, PodPacketPayload::new(), deadline)
+                    .await
+                {
+                    Ok(Some(_)) => DeviceEvent::Heartbeat(handle.id, start.elapsed()),
+                    _ => DeviceEvent::HeartbeatMissed(handle.id),
+                };
+
+                if let Err(e) = tx_device.send(event).await {
+                    eprintln!("heartbeat->pod failed: {}", e);
+                }
+            });
+        }
+    }
+
+    async fn heartbeat_ok(&mut self, id: String, latency: Duration) {
+        self.device_status
+            .lock()
+            .await
+            .entry(id)
+            .or_default()
+            .latency_ms = Some(latency.as_secs_f32() * 1000.0);
+    }
+
+    /// Ids of the devices that faulted or, if critical, missed a heartbeat since the pod was locked
+    async fn faulted_devices(&self) -> Vec<String> {
+        let device_status = self.device_status.lock().await;
+        self.conn_list
+            .iter()
+            .filter(|handle| device_status.get(&handle.id).is_some_and(|s| s.faulted))
+            .map(|handle| handle.id.clone())
+            .collect()
+    }
+
+    /// Escalate a missed heartbeat from a critical device
+    /// Moving -> emergency braking through emerg_svc, Locked -> mark the device faulted
+    async fn heartbeat_missed(&mut self, id: String) {
+        let critical = {
+            let mut device_status = self.device_status.lock().await;
+            let status = device_status.entry(id.clone()).or_default();
+            status.missed_beats += 1;
+            status.critical
+        };
+        eprintln!("pod_conn_svc: device {} missed heartbeat", id);
+
+        if !critical {
+            return;
+        }
+
+        let (moving, locked) = match *self.pod_state.lock().await {
+            PodState::Moving => (true, false),
+            PodState::Locked => (false, true),
+            _ => (false, false),
+        };
+
+        if moving {
+            report_fault(
+                &self.tx_fault,
+                format!("critical device {} missed heartbeat", id),
+            );
+        } else if locked {
+            self.device_status
+                .lock()
+                .await
+                .entry(id)
+                .or_default()
+                .faulted = true;
+        }
+    }
+
+    /// Handle an unsolicited message from a device
+    /// Faults go to emerg_svc, which brakes if the pod is moving, and mark the device faulted,
+    /// every message is then passed on to tele_svc
+    async fn device_message(&mut self, msg: DeviceMessage) {
+        {
+            let mut device_status = self.device_status.lock().await;
+            let status = device_status.entry(msg.device_id.clone()).or_default();
+            match msg.severity {
+                Severity::Fault => {
+                    status.faults += 1;
+                    status.faulted = true;
+                    status.last_fault = Some(msg.message.clone());
+                }
+                Severity::Warning => status.warnings += 1,
+                Severity::Log => (),
+            }
+        }
+
+        match msg.severity {
+            Severity::Fault => {
+                eprintln!(
+                    "pod_conn_svc: device {} reported fault {}: {}",
+                    msg.device_id, msg.code, msg.message
+                );
+                report_fault(
+                    &self.tx_fault,
+                    format!(
+                        "device {} reported fault {}: {}",
+                        msg.device_id, msg.code, msg.message
+                    ),
+                );
+            }
+            Severity::Warning => eprintln!(
+                "pod_conn_svc: device {} reported warning {}: {}",
+                msg.device_id, msg.code, msg.message
+            ),
+            Severity::Log => println!(
+                "pod_conn_svc: device {} log {}: {}",
+                msg.device_id, msg.code, msg.message
+            ),
+        }
+
+        if let Err(e) = self.tx_tele_msg.send(msg).await {
+            eprintln!("pod->tele failed: {}", e);
+        }
+    }
+
+    async fn set_conn_state(&self, id: &str, conn_state: ConnState) {
+        self.device_status
+            .lock()
+            .await
+            .entry(s!(id))
+            .or_default()
+            .conn_state = conn_state;
+    }
+}
+
+/// Build the single ACK returned to ctrl_svc after a broadcast command
+/// cmd_type 0 signals that at least one device failed
+fn ack(cmd: u8, ok: bool) -> PodPacket {
+    let cmd_type = if ok { cmd } else { 0 };
+    PodPacket::new(cmd_type, encode_payload(PodPacketPayload::new()))
+}
+
+/// Ask a device to push the subscribed fields at the subscribed rate
+/// Every field must have been declared by the device during discovery
+async fn subscribe(
+    handle: &DeviceHandle,
+    fields: &[FieldSchema],
+    sub: &Subscription,
+    deadline: Duration,
+) -> Result<(), String> {
+    if sub.rate_hz == 0 || sub.rate_hz > MAX_PUSH_RATE_HZ {
+        return Err(format!(
+            "subscription rate {}Hz not in 1..={}Hz",
+            sub.rate_hz, MAX_PUSH_RATE_HZ
+        ));
+    }
+    if let Some(name) = sub
+        .fields
+        .iter()
+        .find(|name| !fields.iter().any(|f| &f.name == *name))
+    {
+        return Err(format!("subscribed field {} was not discovered", name));
+    }
+
+    let mut payload = PodPacketPayload::new();
+    payload.target_id = handle.id.clone();
+    payload.field_names = sub.fields.clone();
+    payload.rate_hz = sub.rate_hz;
+
+    match handle.request(CMD_SUBSCRIBE, payload, deadline).await {
+        Ok(Some(resp)) if resp.cmd_type == CMD_SUBSCRIBE => {
+            println!(
+                "pod_conn_svc: device {} pushing {} field(s) at {}Hz",
+                handle.id,
+                sub.fields.len(),
+                sub.rate_hz
+            );
+            Ok(())
+        }
+        Ok(Some(resp)) if resp.cmd_type == 0 => Err(s!("subscription rejected by device")),
+        Ok(Some(resp)) => Err(format!("answered subscription with cmd {}", resp.cmd_type)),
+        Ok(None) => Ok(()),
+        Err(e) => Err(s!(e)),
+    }
+}
+
+/// Build the payload for a device specific command requested by link_svc
+/// The command must have been declared by the target device during discovery,
+/// and its arguments are converted to their declared types
+async fn device_cmd_payload(
+    device_commands: &Mutex<HashMap<String, Vec<CommandSchema>>>,
+    req: PodPacketPayload,
+) -> Result<PodPacketPayload, String> {
+    let device_commands = device_commands.lock().await;
+    let schema = device_commands
+        .get(&req.target_id)
+        .and_then(|cmds| cmds.iter().find(|c| c.code == req.target_cmd_code))
+        .ok_or_else(|| {
+            format!(
+                "device {} has no command {}",
+                req.target_id, req.target_cmd_code
+            )
+        })?;
+
+    let mut payload = PodPacketPayload::new();
+    payload.args = schema.check_args(&req.args)?;
+    payload.target_id = req.target_id;
+    payload.target_cmd_code = req.target_cmd_code;
+
+    Ok(payload)
+}
+
+/// Send a device specific command requested by link_svc and wait for the device's answer,
+/// for no longer than deadline
+async fn device_cmd(
+    handle: Option<DeviceHandle>,
+    device_commands: &Mutex<HashMap<String, Vec<CommandSchema>>>,
+    device_status: &Mutex<HashMap<String, DeviceStatus>>,
+    deadline: Duration,
+    req: PodPacketPayload,
+) -> CommandResult {
+    let device_id = req.target_id.clone();
+    let cmd_code = req.target_cmd_code;
+
+    let payload = match device_cmd_payload(device_commands, req).await {
+        Ok(payload) => payload,
+        Err(e) => return CommandResult::failed(device_id, cmd_code, e),
+    };
+
+    let handle = match handle {
+        Some(handle) => handle,
+        None => {
+            let e = format!("device {} is not connected", device_id);
+            return CommandResult::failed(device_id, cmd_code, e);
+        }
+    };
+
+    let start = Instant::now();
+    let res = handle.request(cmd_code, payload, deadline).await;
+    let elapsed_ms = Some(start.elapsed().as_secs_f32() * 1000.0);
+
+    let mut result = CommandResult::failed(device_id, cmd_code, s!(""));
+    result.elapsed_ms = elapsed_ms;
+
+    let resp = match res {
+        Ok(Some(resp)) => resp,
+        Ok(None) => {
+            result.ok = true;
+            result.error = None;
+            return result;
+        }
+        Err(e) => {
+            result.timed_out = matches!(e, DeviceError::TimedOut(_));
+            result.error = Some(s!(e));
+            return result;
+        }
+    };
+
+    let payload = match decode_payload(resp.payload) {
+        Ok(payload) => payload,
+        Err(e) => {
+            record_malformed(device_status, &result.device_id, &e).await;
+            result.error = Some(s!(e));
+            return result;
+        }
+    };
+
+    if resp.cmd_type == cmd_code {
+        result.ok = true;
+        result.error = None;
+        result.values = payload
+            .field_names
+            .into_iter()
+            .zip(payload.telemetry_data)
+            .collect();
+    } else if resp.cmd_type == 0 {
+        result.error_code = Some(payload.target_cmd_code);
+        result.error = Some(s!("rejected by device"));
+    } else {
+        result.error = Some(format!("device answered with cmd {}", resp.cmd_type));
+    }
+
+    result
+}
+
+/// Pass a fault to emerg_svc without waiting on it
+/// emerg_svc may itself be waiting on pod_conn_svc to brake, so a full queue is not waited on,
+/// the faults already queued will request braking
+fn report_fault(tx_fault: &Sender<String>, reason: String) {
+    match tx_fault.try_send(reason) {
+        Ok(()) => {}
+        Err(TrySendError::Full(reason)) => {
+            eprintln!(
+                "pod_conn_svc: braking already requested, not queueing {}",
+                reason
+            )
+        }
+        Err(TrySendError::Closed(_)) => eprintln!("pod->emerg failed"),
+    }
+}
+
+/// Count pushed readings from the device that could not be queued
+/// Reported once every PUSH_QUEUE_SIZE drops, since a backlog drops readings at the push rate
+async fn record_dropped(device_status: &Mutex<HashMap<String, DeviceStatus>>, id: &str) {
+    let mut device_status = device_status.lock().await;
+    let status = device_status.entry(s!(id)).or_default();
+
+    status.dropped_samples += 1;
+    if status.dropped_samples as usize % PUSH_QUEUE_SIZE == 1 {
+        eprintln!(
+            "pod_conn_svc: dropping pushed telemetry from device {} ({} total)",
+            id, status.dropped_samples
+        );
+    }
+}
+
+/// Count a malformed or corrupted frame against the device and report it
+async fn record_malformed(
+    device_status: &Mutex<HashMap<String, DeviceStatus>>,
+    id: &str,
+    e: &PodPacketError,
+) {
+    let mut device_status = device_status.lock().await;
+    let status = device_status.entry(s!(id)).or_default();
+
+    let total = match e {
+        PodPacketError::Corrupted => {
+            status.corrupted_frames += 1;
+            status.corrupted_frames
+        }
+        _ => {
+            status.malformed_frames += 1;
+            status.malformed_frames
+        }
+    };
+
+    eprintln!(
+        "pod_conn_svc: rejected frame from device {} ({} total): {}",
+        id, total, e
+    );
+}
+
+/// Check a telemetry response against the fields the device advertised during discovery
+/// and convert each numeric value for tele_svc
+/// Devices do not report bounds, so value_lower and value_upper are left at 0
+fn read_telemetry(
+    resp: PodPacket,
+    fields: &[FieldSchema],
+) -> Result<Vec<TelemetryData>, PodPacketError> {
+    let payload = decode_payload(resp.payload)?;
+
+    if payload.telemetry_data.len() != fields.len() {
+        return Err(PodPacketError::Malformed(format!(
+            "expected {} telemetry values, received {}",
+            fields.len(),
+            payload.telemetry_data.len()
+        )));
+    }
+
+    let mut tele_data = Vec::new();
+    for (field, value) in fields.iter().zip(payload.telemetry_data) {
+        if value.field_type() != field.field_type {
+            return Err(PodPacketError::Malformed(format!(
+                "field {} declared as {:?}, received {:?}",
+                field.name,
+                field.field_type,
+                value.field_type()
+            )));
+        }
+
+        // string fields have no numeric reading to report
+        if let Some(v) = value.as_f64() {
+            tele_data.push(TelemetryData::new(field.name.clone(), v as f32, 0.0, 0.0));
+        }
+    }
+
+    Ok(tele_data)
+}
+
+/// Check readings pushed by a device against the fields it advertised during discovery
+/// Unlike a polled response, a push may carry any subset of the fields, in any order
+fn read_pushed(
+    id: &str,
+    payload: PodPacketPayload,
+    fields: &[FieldSchema],
+    time_ms: u64,
+) -> Result<Vec<TelemetrySample>, PodPacketError> {
+    if payload.field_names.len() != payload.telemetry_data.len() {
+        return Err(PodPacketError::Malformed(format!(
+            "{} pushed field names but {} values",
+            payload.field_names.len(),
+            payload.telemetry_data.len()
+        )));
+    }
+
+    let mut samples = Vec::new();
+    for (name, value) in payload.field_names.into_iter().zip(payload.telemetry_data) {
+        match fields.iter().find(|f| f.name == name) {
+            Some(field) if field.field_type == value.field_type() => {}
+            Some(field) => {
+                return Err(PodPacketError::Malformed(format!(
+                    "field {} declared as {:?}, received {:?}",
+                    name,
+                    field.field_type,
+                    value.field_type()
+                )))
+            }
+            None => {
+                return Err(PodPacketError::Malformed(format!(
+                    "pushed undeclared field {}",
+                    name
+                )))
+            }
+        }
+
+        samples.push(TelemetrySample {
+            device_id: s!(id),
+            field: name,
+            value,
+            time_ms,
+        });
+    }
+
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn burst_of_faults_does_not_block() {
+        // nothing reads the queue, as while emerg_svc waits on pod_conn_svc to brake
+        let (tx_fault, mut rx_fault) = mpsc::channel::<String>(32);
+
+        for i in 0..100 {
+            report_fault(&tx_fault, format!("fault {}", i));
+        }
+
+        let mut queued = 0;
+        while rx_fault.try_recv().is_ok() {
+            queued += 1;
+        }
+        assert_eq!(queued, 32);
+    }
+}
@@ -0,0 +1,189 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+    time::{timeout, Duration},
+};
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{self, ClientConfig, RootCertStore, ServerName},
+    TlsConnector,
+};
+
+use super::device_conn::{DeviceError, DeviceLink};
+
+/// Longest wait for the TCP connection and again for the TLS handshake with a device,
+/// so an unreachable device cannot stall connecting to the others
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How the link to a single device is secured, stored per device id
+///
+/// Kept in memory next to device_list, which is not persisted either,
+/// so it is lost on restart along with the device configuration and has to be set again
+/// when the devices are added back. Until then devices are connected over plain TCP with a warning.
+///
+/// Devices are authenticated with certificates, since rustls does not offer TLS pre-shared keys.
+/// A device with its own self-signed (non-CA) certificate is pinned
+/// by passing that certificate as ca_cert.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub enum LinkSecurity {
+    /// plain TCP, the device is not authenticated
+    #[default]
+    Plain,
+    /// TLS, the device must present a certificate for server_name that chains to ca_cert
+    Tls {
+        // PEM encoded
+        ca_cert: String,
+        server_name: String,
+        // PEM encoded certificate chain and private key presented by the pod,
+        // for devices that also authenticate the pod
+        client_cert: Option<String>,
+        client_key: Option<String>,
+    },
+}
+
+/// Connection to a device, encrypted or not depending on its LinkSecurity
+pub enum DeviceStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+/// Address and LinkSecurity of a single device, reconnected to with connect
+pub struct SecureLink {
+    pub addr: String,
+    pub security: LinkSecurity,
+}
+
+impl DeviceLink for SecureLink {
+    type Stream = DeviceStream;
+
+    async fn open(&self) -> Result<DeviceStream, DeviceError> {
+        connect(&self.addr, &self.security).await
+    }
+}
+
+/// Open a connection to the device at addr and authenticate it
+/// Returns DeviceError::Unauthenticated if the device's credentials are rejected
+/// and DeviceError::Io if the device cannot be reached within CONNECT_TIMEOUT
+pub async fn connect(addr: &str, security: &LinkSecurity) -> Result<DeviceStream, DeviceError> {
+    let stream = match timeout(CONNECT_TIMEOUT, TcpStream::connect(addr)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => return Err(DeviceError::Io(s!(e))),
+        Err(_) => return Err(DeviceError::Io(s!("connect timed out"))),
+    };
+
+    match security {
+        LinkSecurity::Plain => Ok(DeviceStream::Plain(stream)),
+        LinkSecurity::Tls {
+            ca_cert,
+            server_name,
+            client_cert,
+            client_key,
+        } => {
+            let config = client_config(ca_cert, client_cert.as_deref(), client_key.as_deref())
+                .map_err(|e| DeviceError::Unauthenticated(format!("invalid credentials: {}", e)))?;
+            let name = ServerName::try_from(server_name.as_str())
+                .map_err(|e| DeviceError::Unauthenticated(format!("invalid server name: {}", e)))?;
+
+            let tls = TlsConnector::from(Arc::new(config)).connect(name, stream);
+            match timeout(CONNECT_TIMEOUT, tls).await {
+                Ok(Ok(stream)) => Ok(DeviceStream::Tls(Box::new(stream))),
+                Ok(Err(e)) => Err(DeviceError::Unauthenticated(s!(e))),
+                Err(_) => Err(DeviceError::Io(s!("TLS handshake timed out"))),
+            }
+        }
+    }
+}
+
+fn client_config(
+    ca_cert: &str,
+    client_cert: Option<&str>,
+    client_key: Option<&str>,
+) -> Result<ClientConfig, String> {
+    let mut roots = RootCertStore::empty();
+    for cert in read_certs(ca_cert)? {
+        roots.add(&cert).map_err(|e| s!(e))?;
+    }
+
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots);
+
+    match (client_cert, client_key) {
+        (Some(cert), Some(key)) => config
+            .with_single_cert(read_certs(cert)?, read_key(key)?)
+            .map_err(|e| s!(e)),
+        (None, None) => Ok(config.with_no_client_auth()),
+        _ => Err(s!("client_cert and client_key must be given together")),
+    }
+}
+
+/// Every certificate in a PEM string
+pub fn read_certs(pem: &str) -> Result<Vec<rustls::Certificate>, String> {
+    let certs = rustls_pemfile::certs(&mut pem.as_bytes()).map_err(|e| s!(e))?;
+    if certs.is_empty() {
+        return Err(s!("no certificate found"));
+    }
+
+    Ok(certs.into_iter().map(rustls::Certificate).collect())
+}
+
+/// The first private key in a PEM string
+pub fn read_key(pem: &str) -> Result<rustls::PrivateKey, String> {
+    let mut reader = pem.as_bytes();
+    loop {
+        match rustls_pemfile::read_one(&mut reader).map_err(|e| s!(e))? {
+            Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => return Ok(rustls::PrivateKey(key)),
+            Some(_) => {}
+            None => return Err(s!("no private key found")),
+        }
+    }
+}
+
+impl AsyncRead for DeviceStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            DeviceStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            DeviceStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for DeviceStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            DeviceStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            DeviceStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            DeviceStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            DeviceStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            DeviceStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            DeviceStream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
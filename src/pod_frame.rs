@@ -0,0 +1,365 @@
+use anyhow::{bail, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::pod_packet::{decode, encode, PodPacket, PodPacketError};
+
+/// Size of the length header that precedes every frame body
+pub const HEADER_LEN: usize = 4;
+
+/// Size of the CRC32 that follows every frame body
+pub const CHECKSUM_LEN: usize = 4;
+
+/// Default upper bound for a single frame body
+/// Large enough for discovery responses from devices with many telemetry fields
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// Length-prefixed framing for PodPackets exchanged with embedded devices
+///
+/// Every frame is a big-endian u32 body length, the bincode-encoded PodPacket,
+/// then a big-endian CRC32 of the body.
+/// Frames whose checksum does not match are rejected with PodPacketError::Corrupted.
+/// The length header may be what was corrupted, so the stream cannot be trusted afterwards
+/// and the connection is dropped and re-established.
+/// Devices verify every frame the pod sends the same way and report a corrupted one
+/// with CMD_CORRUPTED before closing the connection.
+/// Bytes read from the stream are buffered until a complete frame is available,
+/// so partial reads are reassembled and coalesced frames are split apart.
+pub struct PodFrameCodec {
+    pub max_frame_size: usize,
+    buf: Vec<u8>,
+}
+
+impl PodFrameCodec {
+    pub fn new(max_frame_size: usize) -> Self {
+        Self {
+            max_frame_size,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Wrap a frame body in its length header and checksum
+    pub fn encode_frame(&self, body: &[u8]) -> Result<Vec<u8>> {
+        if body.len() > self.max_frame_size {
+            bail!(
+                "frame of {} bytes exceeds maximum of {} bytes",
+                body.len(),
+                self.max_frame_size
+            );
+        }
+
+        let mut frame = Vec::with_capacity(HEADER_LEN + body.len() + CHECKSUM_LEN);
+        frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        frame.extend_from_slice(body);
+        frame.extend_from_slice(&crc32fast::hash(body).to_be_bytes());
+
+        Ok(frame)
+    }
+
+    /// Append received bytes to the reassembly buffer
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Take the next complete frame body out of the reassembly buffer
+    /// Returns None if more bytes are needed
+    pub fn decode_frame(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.buf.len() < HEADER_LEN {
+            return Ok(None);
+        }
+
+        let mut header = [0; HEADER_LEN];
+        header.copy_from_slice(&self.buf[..HEADER_LEN]);
+        let len = u32::from_be_bytes(header) as usize;
+
+        if len > self.max_frame_size {
+            bail!(
+                "frame of {} bytes exceeds maximum of {} bytes",
+                len,
+                self.max_frame_size
+            );
+        }
+
+        let frame_len = HEADER_LEN + len + CHECKSUM_LEN;
+        if self.buf.len() < frame_len {
+            return Ok(None);
+        }
+
+        let body = self.buf[HEADER_LEN..HEADER_LEN + len].to_vec();
+        let mut checksum = [0; CHECKSUM_LEN];
+        checksum.copy_from_slice(&self.buf[HEADER_LEN + len..frame_len]);
+        self.buf.drain(..frame_len);
+
+        if u32::from_be_bytes(checksum) != crc32fast::hash(&body) {
+            return Err(PodPacketError::Corrupted.into());
+        }
+
+        Ok(Some(body))
+    }
+
+    /// Read whatever bytes are available into the reassembly buffer
+    /// Cancel safe, no bytes are lost if the read is dropped before it completes
+    pub async fn fill<R: AsyncRead + Unpin>(&mut self, stream: &mut R) -> Result<()> {
+        let mut chunk = [0; 1024];
+
+        let size = stream.read(&mut chunk).await?;
+        if size == 0 {
+            bail!("connection closed by device");
+        }
+        self.extend(&chunk[..size]);
+
+        Ok(())
+    }
+
+    /// Read from the stream until a complete frame body is available
+    pub async fn read_frame<R: AsyncRead + Unpin>(&mut self, stream: &mut R) -> Result<Vec<u8>> {
+        loop {
+            if let Some(body) = self.decode_frame()? {
+                return Ok(body);
+            }
+
+            self.fill(stream).await?;
+        }
+    }
+
+    /// Write a single frame body to the stream
+    pub async fn write_frame<W: AsyncWrite + Unpin>(
+        &self,
+        stream: &mut W,
+        body: &[u8],
+    ) -> Result<()> {
+        let frame = self.encode_frame(body)?;
+        stream.write_all(&frame).await?;
+        // encrypted streams buffer writes until flushed
+        stream.flush().await?;
+
+        Ok(())
+    }
+}
+
+/// Device stream paired with its frame codec
+/// Sends and receives whole PodPackets
+pub struct FramedConn<S> {
+    stream: S,
+    codec: PodFrameCodec,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> FramedConn<S> {
+    pub fn new(stream: S, max_frame_size: usize) -> Self {
+        Self {
+            stream,
+            codec: PodFrameCodec::new(max_frame_size),
+        }
+    }
+
+    pub async fn send(&mut self, pkt: PodPacket) -> Result<()> {
+        self.codec.write_frame(&mut self.stream, &encode(pkt)).await
+    }
+
+    /// Cancel safe, a partially received frame stays buffered for the next call
+    pub async fn recv(&mut self) -> Result<PodPacket> {
+        Ok(decode(self.recv_frame().await?)?)
+    }
+
+    /// Receive the next frame body without decoding it
+    /// Cancel safe, a partially received frame stays buffered for the next call
+    pub async fn recv_frame(&mut self) -> Result<Vec<u8>> {
+        self.codec.read_frame(&mut self.stream).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pod_packet::{peek_seq, MAX_PACKET_SIZE, PACKET_ID, PROTOCOL_VERSION};
+
+    fn frames(bodies: &[&[u8]]) -> Vec<u8> {
+        let codec = PodFrameCodec::new(DEFAULT_MAX_FRAME_SIZE);
+        bodies
+            .iter()
+            .flat_map(|body| codec.encode_frame(body).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn reassembles_split_frame() {
+        let mut codec = PodFrameCodec::new(DEFAULT_MAX_FRAME_SIZE);
+        let data = frames(&[b"discovery response"]);
+
+        // one byte at a time, the frame is only complete with its last byte
+        for byte in &data[..data.len() - 1] {
+            codec.extend(&[*byte]);
+            assert!(codec.decode_frame().unwrap().is_none());
+        }
+        codec.extend(&data[data.len() - 1..]);
+
+        assert_eq!(
+            codec.decode_frame().unwrap().unwrap(),
+            b"discovery response"
+        );
+        assert!(codec.decode_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn splits_coalesced_frames() {
+        let mut codec = PodFrameCodec::new(DEFAULT_MAX_FRAME_SIZE);
+        codec.extend(&frames(&[b"first", b"", b"third"]));
+
+        assert_eq!(codec.decode_frame().unwrap().unwrap(), b"first");
+        assert_eq!(codec.decode_frame().unwrap().unwrap(), b"");
+        assert_eq!(codec.decode_frame().unwrap().unwrap(), b"third");
+        assert!(codec.decode_frame().unwrap().is_none());
+    }
+
+    fn is_corrupted(res: Result<Option<Vec<u8>>>) -> bool {
+        matches!(
+            res.unwrap_err().downcast_ref(),
+            Some(PodPacketError::Corrupted)
+        )
+    }
+
+    #[test]
+    fn rejects_corrupted_body() {
+        let mut codec = PodFrameCodec::new(DEFAULT_MAX_FRAME_SIZE);
+        let mut data = frames(&[b"telemetry"]);
+        data[HEADER_LEN + 2] ^= 0xff;
+        codec.extend(&data);
+
+        assert!(is_corrupted(codec.decode_frame()));
+    }
+
+    #[test]
+    fn rejects_corrupted_length_header() {
+        let mut codec = PodFrameCodec::new(DEFAULT_MAX_FRAME_SIZE);
+        let mut data = frames(&[b"first", b"second"]);
+        // a shorter length swallows the body into the checksum
+        data[HEADER_LEN - 1] -= 2;
+        codec.extend(&data);
+
+        assert!(is_corrupted(codec.decode_frame()));
+    }
+
+    #[test]
+    fn rejects_oversized_frames() {
+        let mut codec = PodFrameCodec::new(8);
+        assert!(codec.encode_frame(&[0; 9]).is_err());
+
+        // refused from the header alone, before the body arrives
+        codec.extend(&9u32.to_be_bytes());
+        assert!(codec.decode_frame().is_err());
+    }
+
+    fn packet(seq: u32) -> PodPacket {
+        let mut pkt = PodPacket::new(1, vec![1, 2, 3]);
+        pkt.seq = seq;
+        pkt
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut pkt = packet(1);
+        pkt.packet_id = s!("NOTOPENLINK");
+
+        match decode(encode(pkt)) {
+            Err(PodPacketError::BadMagic(id)) => assert_eq!(id, "NOTOPENLINK"),
+            _ => panic!("expected BadMagic"),
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_versions() {
+        for version in [0, PROTOCOL_VERSION + 1] {
+            let mut pkt = packet(7);
+            pkt.version = version;
+            let body = encode(pkt);
+
+            assert!(matches!(
+                decode(body.clone()),
+                Err(PodPacketError::UnsupportedVersion(v)) if v == version
+            ));
+            // the header is still readable, so the command it answers can be failed
+            assert_eq!(peek_seq(&body), Some(7));
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_packets() {
+        let body = encode(packet(1));
+
+        for len in [0, 4, body.len() - 1] {
+            assert!(matches!(
+                decode(body[..len].to_vec()),
+                Err(PodPacketError::Truncated)
+            ));
+        }
+
+        // a length prefix longer than the packet is refused before anything is allocated
+        let mut body = encode(packet(1));
+        body[..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(decode(body), Err(PodPacketError::Truncated)));
+    }
+
+    #[test]
+    fn rejects_oversized_packets() {
+        let pkt = PodPacket::new(1, vec![0; MAX_PACKET_SIZE as usize]);
+
+        assert!(matches!(
+            decode(encode(pkt)),
+            Err(PodPacketError::Oversized(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_packets() {
+        // packet_id is not valid UTF-8
+        let mut body = encode(packet(1));
+        body[8..8 + PACKET_ID.len()].fill(0xff);
+
+        assert!(matches!(decode(body), Err(PodPacketError::Malformed(_))));
+    }
+
+    #[tokio::test]
+    async fn recv_reports_decode_errors() {
+        let (a, b) = tokio::io::duplex(256);
+        let mut tx = FramedConn::new(a, DEFAULT_MAX_FRAME_SIZE);
+        let mut rx = FramedConn::new(b, DEFAULT_MAX_FRAME_SIZE);
+
+        let mut pkt = packet(1);
+        pkt.version = PROTOCOL_VERSION + 1;
+        tx.send(pkt).await.unwrap();
+
+        let err = rx.recv().await.err().unwrap();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(PodPacketError::UnsupportedVersion(_))
+        ));
+
+        // a corrupted frame is reported as such rather than as a decode error
+        let mut frame = tx.codec.encode_frame(&encode(packet(2))).unwrap();
+        frame[HEADER_LEN] ^= 0xff;
+        tx.stream.write_all(&frame).await.unwrap();
+
+        let err = rx.recv().await.err().unwrap();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(PodPacketError::Corrupted)
+        ));
+    }
+
+    #[tokio::test]
+    async fn sends_packets_larger_than_a_single_read() {
+        let (a, b) = tokio::io::duplex(256);
+        let mut tx = FramedConn::new(a, DEFAULT_MAX_FRAME_SIZE);
+        let mut rx = FramedConn::new(b, DEFAULT_MAX_FRAME_SIZE);
+
+        let mut pkt = PodPacket::new(1, vec![7; 5000]);
+        pkt.seq = 42;
+        let send = tokio::spawn(async move { tx.send(pkt).await.unwrap() });
+
+        let pkt = rx.recv().await.unwrap();
+        send.await.unwrap();
+        assert_eq!(pkt.cmd_type, 1);
+        assert_eq!(pkt.seq, 42);
+        assert_eq!(pkt.version, PROTOCOL_VERSION);
+        assert_eq!(pkt.payload, vec![7; 5000]);
+    }
+}
@@ -1,11 +1,85 @@
-use bincode::{deserialize, serialize};
+use bincode::{serialize, ErrorKind, Options};
 use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt, io, ops::RangeInclusive};
+
+/// Magic string carried in the packet_id of every PodPacket
+pub const PACKET_ID: &str = "OPENLINK";
+
+/// Protocol version spoken by this pod
+/// 2 -> typed telemetry values and discovery field types
+/// 3 -> seq echoed in every reply
+/// 4 -> connect-time handshake reporting device version and firmware
+/// 5 -> CRC32 after every frame body
+/// 6 -> typed arguments on device specific commands
+/// 7 -> announcement datagrams for automatic discovery
+/// 8 -> unsolicited fault, warning and log messages sent by devices
+/// 9 -> telemetry subscriptions pushed by devices at a requested rate
+pub const PROTOCOL_VERSION: u8 = 9;
+
+/// Oldest protocol version accepted from a device
+/// Devices reporting a version outside MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION are refused
+///
+/// Every version so far changed the wire encoding and the pod only encodes the newest,
+/// so the connect-time handshake is a version check, not a negotiation.
+/// Lower this only together with encoding that depends on the version a device reported.
+pub const MIN_PROTOCOL_VERSION: u8 = 9;
+
+/// Corrupted frame report, sent by a device with seq 0 when a frame from the pod fails its checksum
+/// The device closes the connection after sending it, since the stream may no longer be aligned
+pub const CMD_CORRUPTED: u8 = 245;
+
+/// Telemetry subscription, asks the device to push the fields in field_names rate_hz times a second
+/// The device sends each reading as a CMD_TELEMETRY frame with seq 0 until it is disconnected,
+/// so subscriptions are repeated after every reconnect. rate_hz 0 cancels the subscription
+pub const CMD_SUBSCRIBE: u8 = 246;
+
+/// Log message, sent unsolicited by a device with seq 0
+/// Carries a device specific code in target_cmd_code and free text in message
+pub const CMD_LOG: u8 = 247;
+
+/// Warning, sent unsolicited by a device with seq 0 when something needs attention but is not unsafe
+pub const CMD_WARNING: u8 = 248;
+
+/// Fault, sent unsolicited by a device with seq 0 when it detects an unsafe condition,
+/// e.g. overcurrent or a local e-stop button being pressed
+/// Triggers emergency braking while the pod is moving
+pub const CMD_FAULT: u8 = 249;
+
+/// Announcement, sent over UDP by a device waiting to be added to the pod
+/// Carries the device id, firmware and the TCP port it accepts pod connections on
+pub const CMD_ANNOUNCE: u8 = 250;
+
+/// Handshake, sent once per connection before any other command
+/// The device replies with the protocol version it speaks and its firmware identity
+pub const CMD_HANDSHAKE: u8 = 251;
+
+/// Telemetry request, answered with the current value of every requested field
+/// Also sent by a device with seq 0 for every reading of a telemetry subscription
+pub const CMD_TELEMETRY: u8 = 252;
+
+/// Heartbeat command, answered immediately by every device
+pub const CMD_HEARTBEAT: u8 = 253;
+
+/// Command codes devices may declare as device specific commands during discovery
+///
+/// Every other code is reserved by the pod:
+/// 0 error reply, 1 discovery, 2 disconnect,
+/// 240..=253 protocol commands (the CMD_ constants above, the rest kept free for later ones),
+/// 254 launch, 255 brake
+/// New protocol commands are taken from 240..=253, never from the device range
+pub const DEVICE_CMDS: RangeInclusive<u8> = 3..=239;
+
+/// Upper bound on the encoded size of a single PodPacket or PodPacketPayload
+pub const MAX_PACKET_SIZE: u64 = 64 * 1024;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct PodPacket {
     pub packet_id: String,
     pub version: u8,
     pub cmd_type: u8,
+    // set by the pod on every command and echoed by the device in its reply
+    // 0 is never used by the pod, devices send unsolicited messages with seq 0
+    pub seq: u32,
     // no timestamp because embedded devices may not have system time
 
     //the payload takes the form of another struct: PodPacketPayload
@@ -16,16 +90,95 @@ pub struct PodPacket {
 impl PodPacket {
     pub fn new(cmd_type: u8, payload: Vec<u8>) -> Self {
         Self {
-            packet_id: "OPENLINK".to_string(),
-            version: 1,
+            packet_id: s![PACKET_ID],
+            version: PROTOCOL_VERSION,
             cmd_type: cmd_type,
+            seq: 0,
             payload: payload,
         }
     }
 }
 
-pub fn decode(pkt: Vec<u8>) -> PodPacket {
-    deserialize(&pkt[..]).unwrap()
+/// Reasons a PodPacket or PodPacketPayload received from a device can be rejected
+#[derive(Debug)]
+pub enum PodPacketError {
+    /// packet_id did not match PACKET_ID
+    BadMagic(String),
+    /// version is not spoken by this pod
+    UnsupportedVersion(u8),
+    /// buffer ended before the packet was complete
+    Truncated,
+    /// frame checksum did not match its body
+    Corrupted,
+    /// a field or list exceeded its allowed size
+    Oversized(String),
+    /// any other bincode failure
+    Malformed(String),
+}
+
+impl fmt::Display for PodPacketError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PodPacketError::BadMagic(id) => write!(f, "bad packet_id {:?}", id),
+            PodPacketError::UnsupportedVersion(v) => write!(f, "unsupported version {}", v),
+            PodPacketError::Truncated => write!(f, "truncated packet"),
+            PodPacketError::Corrupted => write!(f, "checksum mismatch"),
+            PodPacketError::Oversized(field) => write!(f, "oversized {}", field),
+            PodPacketError::Malformed(e) => write!(f, "malformed packet: {}", e),
+        }
+    }
+}
+
+impl Error for PodPacketError {}
+
+impl From<bincode::Error> for PodPacketError {
+    fn from(e: bincode::Error) -> Self {
+        match *e {
+            ErrorKind::Io(ref io) if io.kind() == io::ErrorKind::UnexpectedEof => {
+                PodPacketError::Truncated
+            }
+            ErrorKind::SizeLimit => PodPacketError::Oversized(s!("packet")),
+            e => PodPacketError::Malformed(s!(e)),
+        }
+    }
+}
+
+/// Deserialize with the same wire format as bincode::deserialize,
+/// refusing anything larger than MAX_PACKET_SIZE
+/// bincode ignores size limits when reading from a slice, so the buffer itself is checked.
+/// Every length prefix is read against the bytes left in the buffer,
+/// so a bad one fails as Truncated instead of allocating unbounded memory
+pub fn deserialize_bounded<'a, T: Deserialize<'a>>(buf: &'a [u8]) -> Result<T, PodPacketError> {
+    if buf.len() as u64 > MAX_PACKET_SIZE {
+        return Err(PodPacketError::Oversized(s!("packet")));
+    }
+
+    Ok(bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .deserialize(buf)?)
+}
+
+pub fn decode(pkt: Vec<u8>) -> Result<PodPacket, PodPacketError> {
+    let pkt: PodPacket = deserialize_bounded(&pkt[..])?;
+
+    if pkt.packet_id != PACKET_ID {
+        return Err(PodPacketError::BadMagic(pkt.packet_id));
+    }
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&pkt.version) {
+        return Err(PodPacketError::UnsupportedVersion(pkt.version));
+    }
+
+    Ok(pkt)
+}
+
+/// Sequence number of a packet that failed to decode, if its header can still be read
+/// Lets a malformed reply fail the command it answers instead of leaving it to time out
+pub fn peek_seq(pkt: &[u8]) -> Option<u32> {
+    // packet_id, version, cmd_type and seq, in wire order
+    deserialize_bounded::<(String, u8, u8, u32)>(pkt)
+        .ok()
+        .map(|(_, _, _, seq)| seq)
 }
 
 pub fn encode(pkt: PodPacket) -> Vec<u8> {
@@ -1,6 +1,219 @@
-use bincode::{deserialize, serialize};
+use bincode::serialize;
 use serde::{Deserialize, Serialize};
 
+use std::collections::HashSet;
+
+use crate::pod_packet::{deserialize_bounded, PodPacketError, DEVICE_CMDS};
+
+/// Maximum number of entries in any list carried by a payload
+pub const MAX_PAYLOAD_ENTRIES: usize = 256;
+
+/// Maximum length of a device id, field name or command name
+pub const MAX_NAME_LEN: usize = 64;
+
+/// Maximum length of a string telemetry value
+pub const MAX_STR_LEN: usize = 256;
+
+/// Type of a telemetry field, declared by the device during discovery
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum FieldType {
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+    Bool,
+    Str,
+}
+
+/// Single telemetry value reported by a device
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum FieldValue {
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Bool(bool),
+    Str(String),
+}
+
+impl FieldValue {
+    pub fn field_type(&self) -> FieldType {
+        match self {
+            FieldValue::U8(_) => FieldType::U8,
+            FieldValue::U16(_) => FieldType::U16,
+            FieldValue::U32(_) => FieldType::U32,
+            FieldValue::U64(_) => FieldType::U64,
+            FieldValue::I8(_) => FieldType::I8,
+            FieldValue::I16(_) => FieldType::I16,
+            FieldValue::I32(_) => FieldType::I32,
+            FieldValue::I64(_) => FieldType::I64,
+            FieldValue::F32(_) => FieldType::F32,
+            FieldValue::F64(_) => FieldType::F64,
+            FieldValue::Bool(_) => FieldType::Bool,
+            FieldValue::Str(_) => FieldType::Str,
+        }
+    }
+
+    /// Numeric value of the field, bools map to 0 and 1
+    /// I64 and U64 values beyond 2^53 are rounded to the nearest f64
+    /// Returns None for strings
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            FieldValue::U8(v) => Some(*v as f64),
+            FieldValue::U16(v) => Some(*v as f64),
+            FieldValue::U32(v) => Some(*v as f64),
+            FieldValue::U64(v) => Some(*v as f64),
+            FieldValue::I8(v) => Some(*v as f64),
+            FieldValue::I16(v) => Some(*v as f64),
+            FieldValue::I32(v) => Some(*v as f64),
+            FieldValue::I64(v) => Some(*v as f64),
+            FieldValue::F32(v) => Some(*v as f64),
+            FieldValue::F64(v) => Some(*v),
+            FieldValue::Bool(v) => Some(if *v { 1.0 } else { 0.0 }),
+            FieldValue::Str(_) => None,
+        }
+    }
+
+    /// Integer value of the field
+    /// Returns None for floats, bools and strings
+    pub fn as_i128(&self) -> Option<i128> {
+        match self {
+            FieldValue::U8(v) => Some(*v as i128),
+            FieldValue::U16(v) => Some(*v as i128),
+            FieldValue::U32(v) => Some(*v as i128),
+            FieldValue::U64(v) => Some(*v as i128),
+            FieldValue::I8(v) => Some(*v as i128),
+            FieldValue::I16(v) => Some(*v as i128),
+            FieldValue::I32(v) => Some(*v as i128),
+            FieldValue::I64(v) => Some(*v as i128),
+            _ => None,
+        }
+    }
+
+    /// Integer value converted to the given type, without passing through f64
+    /// Returns None if the type is not numeric or cannot represent the value exactly
+    pub fn from_i128(field_type: FieldType, v: i128) -> Option<FieldValue> {
+        match field_type {
+            FieldType::U8 => u8::try_from(v).ok().map(FieldValue::U8),
+            FieldType::U16 => u16::try_from(v).ok().map(FieldValue::U16),
+            FieldType::U32 => u32::try_from(v).ok().map(FieldValue::U32),
+            FieldType::U64 => u64::try_from(v).ok().map(FieldValue::U64),
+            FieldType::I8 => i8::try_from(v).ok().map(FieldValue::I8),
+            FieldType::I16 => i16::try_from(v).ok().map(FieldValue::I16),
+            FieldType::I32 => i32::try_from(v).ok().map(FieldValue::I32),
+            FieldType::I64 => i64::try_from(v).ok().map(FieldValue::I64),
+            FieldType::F32 if v as f32 as i128 == v => Some(FieldValue::F32(v as f32)),
+            FieldType::F64 if v as f64 as i128 == v => Some(FieldValue::F64(v as f64)),
+            _ => None,
+        }
+    }
+
+    /// Numeric value converted to the given type
+    /// Returns None if the type is not numeric or cannot represent the value exactly
+    /// Integers above 2^53 cannot be told apart as f64, use from_i128 for integer sources
+    pub fn from_f64(field_type: FieldType, v: f64) -> Option<FieldValue> {
+        let int = v.fract() == 0.0;
+        let fits = |min: f64, max: f64| int && v >= min && v <= max;
+        // u64::MAX and i64::MAX round up to the next power of two as f64
+        let fits_below = |min: f64, end: f64| int && v >= min && v < end;
+
+        match field_type {
+            FieldType::U8 if fits(0.0, u8::MAX as f64) => Some(FieldValue::U8(v as u8)),
+            FieldType::U16 if fits(0.0, u16::MAX as f64) => Some(FieldValue::U16(v as u16)),
+            FieldType::U32 if fits(0.0, u32::MAX as f64) => Some(FieldValue::U32(v as u32)),
+            FieldType::U64 if fits_below(0.0, u64::MAX as f64) => Some(FieldValue::U64(v as u64)),
+            FieldType::I8 if fits(i8::MIN as f64, i8::MAX as f64) => Some(FieldValue::I8(v as i8)),
+            FieldType::I16 if fits(i16::MIN as f64, i16::MAX as f64) => {
+                Some(FieldValue::I16(v as i16))
+            }
+            FieldType::I32 if fits(i32::MIN as f64, i32::MAX as f64) => {
+                Some(FieldValue::I32(v as i32))
+            }
+            FieldType::I64 if fits_below(i64::MIN as f64, i64::MAX as f64) => {
+                Some(FieldValue::I64(v as i64))
+            }
+            FieldType::F32 if v.is_finite() => Some(FieldValue::F32(v as f32)),
+            FieldType::F64 if v.is_finite() => Some(FieldValue::F64(v)),
+            _ => None,
+        }
+    }
+}
+
+/// Argument of a device specific command, declared by the device during discovery
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct CommandArg {
+    pub name: String,
+    pub arg_type: FieldType,
+    //inclusive bounds for numeric arguments
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl CommandArg {
+    /// Convert a value supplied by the client to the declared type of this argument
+    /// Fails if the value has the wrong kind, is out of bounds, or cannot be represented exactly
+    pub fn coerce(&self, value: &FieldValue) -> Result<FieldValue, String> {
+        match (self.arg_type, value) {
+            (FieldType::Str, FieldValue::Str(v)) if v.len() > MAX_STR_LEN => {
+                return Err(format!("argument {} is too long", self.name));
+            }
+            (FieldType::Str, FieldValue::Str(_)) | (FieldType::Bool, FieldValue::Bool(_)) => {
+                return Ok(value.clone());
+            }
+            _ => {}
+        }
+
+        let v = match value.as_f64() {
+            Some(v)
+                if !matches!(value, FieldValue::Bool(_))
+                    && !matches!(self.arg_type, FieldType::Bool | FieldType::Str) =>
+            {
+                v
+            }
+            _ => {
+                return Err(format!(
+                    "argument {} expects {:?}, received {:?}",
+                    self.name,
+                    self.arg_type,
+                    value.field_type()
+                ))
+            }
+        };
+
+        if self.min.is_some_and(|min| v < min) || self.max.is_some_and(|max| v > max) {
+            return Err(format!(
+                "argument {} is out of bounds ({} not in {:?}..={:?})",
+                self.name, v, self.min, self.max
+            ));
+        }
+
+        // integers are converted directly, f64 only holds 53 bits exactly
+        let coerced = match value.as_i128() {
+            Some(int) => FieldValue::from_i128(self.arg_type, int),
+            None => FieldValue::from_f64(self.arg_type, v),
+        };
+
+        coerced.ok_or_else(|| {
+            format!(
+                "argument {} cannot hold {} as {:?}",
+                self.name, v, self.arg_type
+            )
+        })
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 
 //template used in discovery packets
@@ -8,9 +221,30 @@ pub struct PodPacketPayload {
     pub target_id: String,
     pub target_cmd_code: u8,
     pub field_names: Vec<String>,
-    pub telemetry_data: Vec<u8>, //assume all data is of type u8 for now (maybe make this Vec<Vec<u8>> later?)
+    //type of each entry in field_names, declared during discovery
+    pub field_types: Vec<FieldType>,
+    //value of each entry in field_names, in the same order
+    pub telemetry_data: Vec<FieldValue>,
     pub command_names: Vec<String>,
     pub command_codes: Vec<u8>,
+    //arguments declared for each entry in command_codes, in the same order
+    pub command_args: Vec<Vec<CommandArg>>,
+    //arguments sent with a device specific command, in declared order
+    pub args: Vec<FieldValue>,
+    //firmware identity reported by the device in its handshake reply
+    pub firmware: String,
+    //TCP port the device accepts pod connections on, sent in announcements
+    pub port: u16,
+    //free text of an unsolicited fault, warning or log message
+    pub message: String,
+    //readings per second requested by a telemetry subscription
+    pub rate_hz: u16,
+}
+
+impl Default for PodPacketPayload {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PodPacketPayload {
@@ -19,17 +253,276 @@ impl PodPacketPayload {
             target_id: s![""],
             target_cmd_code: 0,
             field_names: Vec::new(),
-            telemetry_data: Vec::new(), //assume all data is of type u8 for now (maybe make this Vec<byte array> later?)
+            field_types: Vec::new(),
+            telemetry_data: Vec::new(),
             command_names: Vec::new(),
             command_codes: Vec::new(),
+            command_args: Vec::new(),
+            args: Vec::new(),
+            firmware: s![""],
+            port: 0,
+            message: s![""],
+            rate_hz: 0,
+        }
+    }
+
+    /// Reject payloads whose lists or names exceed the pod's limits
+    fn check_sizes(&self) -> Result<(), PodPacketError> {
+        if self.target_id.len() > MAX_NAME_LEN {
+            return Err(PodPacketError::Oversized(s!("target_id")));
+        }
+        if self.firmware.len() > MAX_NAME_LEN {
+            return Err(PodPacketError::Oversized(s!("firmware")));
+        }
+        if self.message.len() > MAX_STR_LEN {
+            return Err(PodPacketError::Oversized(s!("message")));
+        }
+
+        let lists = [
+            ("field_names", self.field_names.len()),
+            ("field_types", self.field_types.len()),
+            ("telemetry_data", self.telemetry_data.len()),
+            ("command_names", self.command_names.len()),
+            ("command_codes", self.command_codes.len()),
+            ("command_args", self.command_args.len()),
+            ("args", self.args.len()),
+        ];
+        for (name, len) in lists {
+            if len > MAX_PAYLOAD_ENTRIES {
+                return Err(PodPacketError::Oversized(s!(name)));
+            }
+        }
+
+        if self
+            .command_args
+            .iter()
+            .any(|args| args.len() > MAX_PAYLOAD_ENTRIES)
+        {
+            return Err(PodPacketError::Oversized(s!("command_args")));
+        }
+
+        if self
+            .field_names
+            .iter()
+            .chain(self.command_names.iter())
+            .chain(self.command_args.iter().flatten().map(|arg| &arg.name))
+            .any(|name| name.len() > MAX_NAME_LEN)
+        {
+            return Err(PodPacketError::Oversized(s!("name")));
         }
+
+        if self
+            .telemetry_data
+            .iter()
+            .chain(self.args.iter())
+            .any(|value| match value {
+                FieldValue::Str(v) => v.len() > MAX_STR_LEN,
+                _ => false,
+            })
+        {
+            return Err(PodPacketError::Oversized(s!("telemetry value")));
+        }
+
+        Ok(())
+    }
+
+    /// Check that a discovery response describes a usable device
+    /// Lists must line up, names and codes must be unique and no command may use a reserved code
+    pub fn check_discovery(&self) -> Result<(), String> {
+        if self.field_names.len() != self.field_types.len() {
+            return Err(format!(
+                "{} field names but {} field types",
+                self.field_names.len(),
+                self.field_types.len()
+            ));
+        }
+        if self.command_names.len() != self.command_codes.len() {
+            return Err(format!(
+                "{} command names but {} command codes",
+                self.command_names.len(),
+                self.command_codes.len()
+            ));
+        }
+        if self.command_args.len() != self.command_codes.len() {
+            return Err(format!(
+                "{} commands but {} argument lists",
+                self.command_codes.len(),
+                self.command_args.len()
+            ));
+        }
+
+        check_unique("field name", self.field_names.iter())?;
+        check_unique("command name", self.command_names.iter())?;
+        check_unique("command code", self.command_codes.iter())?;
+
+        if let Some(code) = self
+            .command_codes
+            .iter()
+            .find(|code| !DEVICE_CMDS.contains(code))
+        {
+            return Err(format!(
+                "command code {} is reserved by the pod, device commands must be in {}..={}",
+                code,
+                DEVICE_CMDS.start(),
+                DEVICE_CMDS.end()
+            ));
+        }
+
+        for (name, args) in self.command_names.iter().zip(self.command_args.iter()) {
+            check_unique("argument name", args.iter().map(|arg| &arg.name))
+                .map_err(|e| format!("command {}: {}", name, e))?;
+
+            for arg in args.iter() {
+                if let (Some(min), Some(max)) = (arg.min, arg.max) {
+                    if min > max {
+                        return Err(format!(
+                            "command {}: argument {} has min {} above max {}",
+                            name, arg.name, min, max
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(())
     }
 }
 
-pub fn decode_payload(pkt: Vec<u8>) -> PodPacketPayload {
-    deserialize(&pkt[..]).unwrap()
+/// Reject empty or repeated entries in a discovery list
+fn check_unique<T: std::fmt::Display>(
+    what: &str,
+    items: impl Iterator<Item = T>,
+) -> Result<(), String> {
+    let mut seen = HashSet::new();
+    for item in items {
+        let key = s!(item);
+        if key.is_empty() {
+            return Err(format!("empty {}", what));
+        }
+        if !seen.insert(key) {
+            return Err(format!("duplicate {} {}", what, item));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn decode_payload(pkt: Vec<u8>) -> Result<PodPacketPayload, PodPacketError> {
+    let payload: PodPacketPayload = deserialize_bounded(&pkt[..])?;
+    payload.check_sizes()?;
+
+    Ok(payload)
 }
 
 pub fn encode_payload(pkt: PodPacketPayload) -> Vec<u8> {
     serialize(&pkt).unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arg(arg_type: FieldType) -> CommandArg {
+        CommandArg {
+            name: s!("value"),
+            arg_type,
+            min: None,
+            max: None,
+        }
+    }
+
+    #[test]
+    fn coerces_large_integers_exactly() {
+        let big = (1u64 << 53) + 1;
+
+        assert_eq!(
+            arg(FieldType::U64).coerce(&FieldValue::U64(u64::MAX)),
+            Ok(FieldValue::U64(u64::MAX))
+        );
+        assert_eq!(
+            arg(FieldType::I64).coerce(&FieldValue::U64(big)),
+            Ok(FieldValue::I64(big as i64))
+        );
+        assert!(arg(FieldType::F64).coerce(&FieldValue::U64(big)).is_err());
+        assert!(arg(FieldType::U64).coerce(&FieldValue::I64(-1)).is_err());
+    }
+
+    #[test]
+    fn coerces_whole_floats_to_integers() {
+        assert_eq!(
+            arg(FieldType::U8).coerce(&FieldValue::F64(200.0)),
+            Ok(FieldValue::U8(200))
+        );
+        assert!(arg(FieldType::U8).coerce(&FieldValue::F64(2.5)).is_err());
+        assert!(arg(FieldType::U64)
+            .coerce(&FieldValue::F64(u64::MAX as f64))
+            .is_err());
+    }
+
+    fn discovery() -> PodPacketPayload {
+        let mut payload = PodPacketPayload::new();
+        payload.field_names = vec![s!("voltage"), s!("temperature")];
+        payload.field_types = vec![FieldType::F32, FieldType::I16];
+        payload.command_names = vec![s!("reset"), s!("set_limit")];
+        payload.command_codes = vec![3, 239];
+        payload.command_args = vec![Vec::new(), vec![arg(FieldType::U16)]];
+
+        payload
+    }
+
+    #[test]
+    fn accepts_valid_discovery() {
+        assert_eq!(discovery().check_discovery(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_misaligned_discovery_lists() {
+        let mut payload = discovery();
+        payload.field_types.pop();
+        assert!(payload.check_discovery().is_err());
+
+        let mut payload = discovery();
+        payload.command_codes.pop();
+        assert!(payload.check_discovery().is_err());
+
+        let mut payload = discovery();
+        payload.command_args.pop();
+        assert!(payload.check_discovery().is_err());
+    }
+
+    #[test]
+    fn rejects_duplicate_or_empty_names() {
+        let mut payload = discovery();
+        payload.field_names[1] = s!("voltage");
+        assert!(payload.check_discovery().is_err());
+
+        let mut payload = discovery();
+        payload.command_names[0] = s!("");
+        assert!(payload.check_discovery().is_err());
+
+        let mut payload = discovery();
+        payload.command_codes[1] = 3;
+        assert!(payload.check_discovery().is_err());
+
+        let mut payload = discovery();
+        payload.command_args[1].push(arg(FieldType::U8));
+        assert!(payload.check_discovery().is_err());
+    }
+
+    #[test]
+    fn rejects_reserved_command_codes() {
+        for code in [0, 1, 2, 240, 253, 254, 255] {
+            let mut payload = discovery();
+            payload.command_codes[0] = code;
+            assert!(payload.check_discovery().is_err(), "code {}", code);
+        }
+    }
+
+    #[test]
+    fn rejects_inverted_argument_bounds() {
+        let mut payload = discovery();
+        payload.command_args[1][0].min = Some(10.0);
+        payload.command_args[1][0].max = Some(1.0);
+        assert!(payload.check_discovery().is_err());
+    }
+}
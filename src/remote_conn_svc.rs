@@ -2,7 +2,7 @@
 use anyhow::Result;
 use futures_util::stream::StreamExt;
 use quinn::{Endpoint, ServerConfig};
-use std::{error::Error, sync::Arc};
+use std::{error::Error, net::SocketAddr, sync::Arc};
 use tokio::sync::{mpsc::Receiver, mpsc::Sender};
 use tracing::{error, info};
 
@@ -10,7 +10,7 @@ use shared::remote_conn_packet::{decode, encode, RemotePacket};
 
 pub struct RemoteConnSvc {
     pub rx_auth: Receiver<RemotePacket>,
-    pub tx_auth: Sender<RemotePacket>,
+    pub tx_auth: Sender<(RemotePacket, SocketAddr)>,
     pub tx_emerg: Sender<u8>,
 }
 
@@ -68,6 +68,7 @@ impl RemoteConnSvc {
     /// Takes a connecting client and establishes send and receive streams
     async fn handle_connection(&mut self, conn: quinn::Connecting) -> Result<()> {
         let connection = conn.await?;
+        let addr = connection.remote_address();
 
         async {
             info!("established");
@@ -83,7 +84,7 @@ impl RemoteConnSvc {
                     }
                     Ok(s) => s,
                 };
-                let fut = self.handle_request(stream);
+                let fut = self.handle_request(stream, addr);
                 if let Err(e) = fut.await {
                     error!("failed: {}", e.to_string());
                 }
@@ -100,6 +101,7 @@ impl RemoteConnSvc {
     async fn handle_request(
         &mut self,
         (mut send, recv): (quinn::SendStream, quinn::RecvStream),
+        addr: SocketAddr,
     ) -> Result<()> {
         let req = match recv.read_to_end(64 * 1024).await {
             Ok(req) => req,
@@ -112,7 +114,7 @@ impl RemoteConnSvc {
         if pkt.cmd_type == 0 {
             resp = encode(pkt);
         } else {
-            resp = self.process_packet(pkt).await.unwrap();
+            resp = self.process_packet(pkt, addr).await.unwrap();
         }
 
         match send.write_all(&resp).await {
@@ -128,12 +130,12 @@ impl RemoteConnSvc {
         Ok(())
     }
 
-    /// Send the packet to the auth service
+    /// Send the packet to the auth service, along with the address of the client that sent it
     /// Receive the result from the auth service and update timestamp
     /// If request to auth_svc errored, return the error as the payload and update timestamp
     /// Return packet as buffer
-    async fn process_packet(&mut self, pkt: RemotePacket) -> Result<Vec<u8>> {
-        let pkt = match self.tx_auth.send(pkt).await {
+    async fn process_packet(&mut self, pkt: RemotePacket, addr: SocketAddr) -> Result<Vec<u8>> {
+        let pkt = match self.tx_auth.send((pkt, addr)).await {
             Ok(()) => {
                 let resp = self.rx_auth.recv().await.unwrap();
                 RemotePacket::new_with_auth(resp.cmd_type, resp.payload, resp.token)
@@ -0,0 +1,156 @@
+use serde::{Deserialize, Serialize};
+
+/// ugroup of the admin role, which is allowed every command whatever the policy says
+/// so an admin can never lock themselves out of editing it
+pub const ADMIN_ROLE: u8 = 255;
+
+/// ugroup given to requests without a valid token, never allowed anything
+/// Users may also be given it to suspend them without deleting their account
+pub const NO_ROLE: u8 = 0;
+
+/// Named role a user belongs to, stored in the roles table
+/// The id is the ugroup carried by users and tokens
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Role {
+    pub id: u8,
+    pub name: String,
+}
+
+/// Policy entry allowing a role to use every cmd_type in first_cmd..=last_cmd
+/// A single command is allowed by setting first_cmd and last_cmd to the same code
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Permission {
+    pub role: u8,
+    pub first_cmd: u8,
+    pub last_cmd: u8,
+}
+
+/// Every role and what each one may do, loaded from the database by auth_svc
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Policy {
+    pub roles: Vec<Role>,
+    pub permissions: Vec<Permission>,
+}
+
+impl Policy {
+    /// Check whether a user of the given ugroup may send cmd
+    pub fn allows(&self, ugroup: u8, cmd: u8) -> bool {
+        match ugroup {
+            NO_ROLE => false,
+            ADMIN_ROLE => true,
+            _ => self
+                .permissions
+                .iter()
+                .any(|p| p.role == ugroup && (p.first_cmd..=p.last_cmd).contains(&cmd)),
+        }
+    }
+}
+
+/// Roles created with a new database
+/// Further roles, e.g. a test engineer, are added by an admin at runtime
+pub fn default_roles() -> Vec<Role> {
+    vec![
+        Role {
+            id: 1,
+            name: s!("mission control"),
+        },
+        Role {
+            id: 2,
+            name: s!("software"),
+        },
+        Role {
+            id: 3,
+            name: s!("viewer"),
+        },
+        Role {
+            id: ADMIN_ROLE,
+            name: s!("admin"),
+        },
+    ]
+}
+
+/// Policy created with a new database, matching the command ranges of each service
+/// 0..=31 auth, 32..=63 link, 64..=127 ctrl, 128..=159 tele, 160..=195 database
+pub fn default_permissions() -> Vec<Permission> {
+    let allow = |role, first_cmd, last_cmd| Permission {
+        role,
+        first_cmd,
+        last_cmd,
+    };
+
+    vec![
+        // mission control runs the pod and watches telemetry
+        allow(1, 64, 127),
+        allow(1, 128, 159),
+        // software configures devices and watches telemetry
+        allow(2, 32, 63),
+        allow(2, 128, 159),
+        // viewers only watch telemetry
+        allow(3, 128, 159),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> Policy {
+        Policy {
+            roles: default_roles(),
+            permissions: default_permissions(),
+        }
+    }
+
+    #[test]
+    fn allows_commands_in_granted_ranges() {
+        let policy = policy();
+
+        assert!(policy.allows(1, 64));
+        assert!(policy.allows(1, 127));
+        assert!(policy.allows(3, 128));
+        assert!(policy.allows(3, 159));
+        assert!(!policy.allows(3, 127));
+        assert!(!policy.allows(3, 160));
+        assert!(!policy.allows(2, 64));
+    }
+
+    #[test]
+    fn allows_single_command_permissions() {
+        let mut policy = policy();
+        policy.permissions.push(Permission {
+            role: 3,
+            first_cmd: 171,
+            last_cmd: 171,
+        });
+
+        assert!(policy.allows(3, 171));
+        assert!(!policy.allows(3, 170));
+        assert!(!policy.allows(3, 172));
+    }
+
+    #[test]
+    fn no_role_is_never_allowed() {
+        let mut policy = policy();
+        policy.permissions.push(Permission {
+            role: NO_ROLE,
+            first_cmd: 0,
+            last_cmd: 255,
+        });
+
+        assert!(!policy.allows(NO_ROLE, 1));
+        assert!(!policy.allows(NO_ROLE, 128));
+    }
+
+    #[test]
+    fn admin_is_always_allowed() {
+        let policy = Policy::default();
+
+        assert!(policy.allows(ADMIN_ROLE, 0));
+        assert!(policy.allows(ADMIN_ROLE, 255));
+    }
+
+    #[test]
+    fn unknown_roles_are_not_allowed() {
+        assert!(!policy().allows(42, 128));
+    }
+}
@@ -1,55 +1,99 @@
 use rand::Rng;
-use std::sync::Arc;
+use std::{collections::VecDeque, sync::Arc};
 use tokio::{
     select,
     sync::{mpsc::Receiver, mpsc::Sender, Mutex},
     time::{self, Duration},
 };
 
-use crate::pod_conn_svc::PodState;
+use crate::pod_conn_svc::{DeviceMessage, PodState, TelemetrySample};
+use crate::user::Identity;
 use shared::{remote_conn_packet::RemotePacket, telemetry::TelemetryData};
 
+/// Number of device messages kept for reporting to the client, older messages are only in the database
+pub const MAX_DEVICE_MSGS: usize = 100;
+
+/// Number of pushed telemetry samples kept for reporting to the client
+pub const MAX_SAMPLES: usize = 2000;
+
+/// Where tele_svc gets its readings from
+pub enum TelemetrySource {
+    /// query every locked device through pod_conn_svc
+    Devices,
+    /// generate random readings within fixed bounds, for running without hardware
+    Simulated,
+}
+
+impl TelemetrySource {
+    /// Simulated if OPENLINK_TELEMETRY=simulated, otherwise real devices
+    pub fn from_env() -> Self {
+        match std::env::var("OPENLINK_TELEMETRY") {
+            Ok(source) if source == "simulated" => TelemetrySource::Simulated,
+            _ => TelemetrySource::Devices,
+        }
+    }
+}
+
 pub struct TelemetrySvc {
     pub pod_state: Arc<Mutex<PodState>>,
     pub tele_data: Vec<TelemetryData>,
+    pub source: TelemetrySource,
+    // latest fault, warning and log messages sent by devices, oldest first
+    pub device_msgs: VecDeque<DeviceMessage>,
+    // latest reading of every field pushed by a subscribed device, keyed by device id and field,
+    // kept across polls and reported alongside the polled readings
+    pub pushed_data: Vec<((String, String), f32)>,
+    // latest pushed readings with their arrival time, oldest first
+    pub samples: VecDeque<TelemetrySample>,
 
-    pub rx_auth: Receiver<RemotePacket>,
+    pub rx_auth: Receiver<(RemotePacket, Identity)>,
     pub tx_auth: Sender<RemotePacket>,
     //pub rx_data: Receiver<u8>,
-    //pub tx_data: Sender<u8>,
+    pub tx_data: Sender<DeviceMessage>,
     //pub rx_emerg: Receiver<u8>,
     //pub tx_emerg: Sender<u8>,
-    //pub rx_pod: Receiver<i32>,
-    //pub tx_pod: Sender<i32>,
+    pub rx_pod: Receiver<Vec<TelemetryData>>,
+    pub tx_pod: Sender<u8>,
+    pub rx_pod_msg: Receiver<DeviceMessage>,
+    pub rx_pod_push: Receiver<Vec<TelemetrySample>>,
 }
 
 impl TelemetrySvc {
     /// Main service task for telemetry service
     pub async fn run(mut self) {
         println!("tele_svc: service running");
-        self.init_fake_telemetry();
+        if let TelemetrySource::Simulated = self.source {
+            println!("tele_svc: using simulated telemetry");
+            self.init_fake_telemetry();
+        }
 
         // repeating interval to query subsystems for telemetry data
         let mut tele_timer = time::interval(Duration::from_secs(1));
 
         loop {
             select! {
-                Some(pkt) = self.rx_auth.recv() => {
+                Some((pkt, sender)) = self.rx_auth.recv() => {
                     let resp = match pkt.cmd_type {
                         128 => self.report_telemetry().await,
-                        _ => RemotePacket::new(0, vec![s!("Command not implemented")])
+                        129 => self.report_samples(),
+                        _ => {
+                            eprintln!("tele_svc: {} sent unknown command {}", sender.username, pkt.cmd_type);
+                            RemotePacket::new(0, vec![s!("Command not implemented")])
+                        }
                     };
 
                     if let Err(e) = self.tx_auth.send(resp).await {
                         eprintln!("tele->auth failed: {}", e);
                     }
                 }
-                _ = tele_timer.tick() => self.get_telemetry().await
+                _ = tele_timer.tick() => self.get_telemetry().await,
+                Some(msg) = self.rx_pod_msg.recv() => self.record_message(msg).await,
+                Some(samples) = self.rx_pod_push.recv() => self.record_samples(samples),
             }
         }
     }
 
-    /// TEMPORARY FUNCTION
+    /// SIMULATION SOURCE
     /// Initializes the tele_data vector with fake measurements, with upper and lower bounds
     fn init_fake_telemetry(&mut self) {
         self.tele_data
@@ -66,7 +110,7 @@ impl TelemetrySvc {
             .push(TelemetryData::new(s!("Battery Current"), 0.0, 5.0, 0.0));
     }
 
-    /// TEMPORARY FUNCTION
+    /// SIMULATION SOURCE
     /// Generates current values of telemetry data from upper and lower bounds
     fn generate_fake_telemetry(&mut self) {
         let mut rng = rand::thread_rng();
@@ -76,7 +120,7 @@ impl TelemetrySvc {
     }
 
     /// Repeating function to ask pod_conn_svc for telemetry
-    /// Currently generates fake telemetry
+    /// Generates fake telemetry instead when running with the simulation source
     async fn get_telemetry(&mut self) {
         let gather = match *self.pod_state.lock().await {
             PodState::Unlocked => false,
@@ -84,15 +128,97 @@ impl TelemetrySvc {
         };
 
         if gather {
-            self.generate_fake_telemetry()
+            match self.source {
+                TelemetrySource::Devices => self.poll_devices().await,
+                TelemetrySource::Simulated => self.generate_fake_telemetry(),
+            }
+        }
+    }
+
+    /// Ask pod_conn_svc to query every locked device
+    /// and replace tele_data with the readings it returns
+    /// Pushed fields are never polled, so they are kept apart in pushed_data
+    async fn poll_devices(&mut self) {
+        if let Err(e) = self.tx_pod.send(1).await {
+            eprintln!("tele->pod failed: {}", e);
+            return;
+        }
+
+        if let Some(tele_data) = self.rx_pod.recv().await {
+            self.tele_data = tele_data;
+        }
+    }
+
+    /// Keep readings pushed by subscribed devices
+    /// and show the latest reading of each field alongside the polled ones
+    fn record_samples(&mut self, samples: Vec<TelemetrySample>) {
+        for sample in samples {
+            if let Some(v) = sample.value.as_f64() {
+                update_reading(
+                    &mut self.pushed_data,
+                    &sample.device_id,
+                    &sample.field,
+                    v as f32,
+                );
+            }
+
+            if self.samples.len() == MAX_SAMPLES {
+                self.samples.pop_front();
+            }
+            self.samples.push_back(sample);
+        }
+    }
+
+    /// Keep a message sent by a device for the client and have database_svc store it
+    async fn record_message(&mut self, msg: DeviceMessage) {
+        if self.device_msgs.len() == MAX_DEVICE_MSGS {
+            self.device_msgs.pop_front();
         }
+        self.device_msgs.push_back(msg.clone());
+
+        if let Err(e) = self.tx_data.send(msg).await {
+            eprintln!("tele->data failed: {}", e);
+        }
+    }
+
+    /// Report the latest pushed readings with their arrival times
+    fn report_samples(&self) -> RemotePacket {
+        let samples = serde_json::to_string(&self.samples).unwrap();
+
+        RemotePacket::new(129, vec![samples])
     }
 
-    /// Report both telemetry data and pod_state
+    /// Report telemetry data, pod_state and the latest device messages
+    /// Polled readings come first, followed by the latest reading of every pushed field
     async fn report_telemetry(&mut self) -> RemotePacket {
         let pod_state = serde_json::to_string(&*self.pod_state.lock().await).unwrap();
-        let telemetry = serde_json::to_string(&self.tele_data).unwrap();
+        // devices do not report bounds, so value_lower and value_upper of pushed fields are left at 0
+        let pushed: Vec<TelemetryData> = self
+            .pushed_data
+            .iter()
+            .map(|((_, field), value)| TelemetryData::new(field.clone(), *value, 0.0, 0.0))
+            .collect();
+        let tele_data: Vec<&TelemetryData> = self.tele_data.iter().chain(pushed.iter()).collect();
+        let telemetry = serde_json::to_string(&tele_data).unwrap();
+        let device_msgs = serde_json::to_string(&self.device_msgs).unwrap();
+
+        RemotePacket::new(128, vec![telemetry, pod_state, device_msgs])
+    }
+}
 
-        RemotePacket::new(128, vec![telemetry, pod_state])
+/// Replace the reading of the same field from the same device, or add it if either is new
+/// Devices may declare fields with the same name, so the field name alone does not identify a reading
+fn update_reading(
+    pushed_data: &mut Vec<((String, String), f32)>,
+    device_id: &str,
+    field: &str,
+    value: f32,
+) {
+    match pushed_data
+        .iter_mut()
+        .find(|((d, f), _)| d == device_id && f == field)
+    {
+        Some((_, v)) => *v = value,
+        None => pushed_data.push(((s!(device_id), s!(field)), value)),
     }
 }
@@ -5,14 +5,31 @@ use tokio::{
     time::{sleep, Duration},
 };
 
+use crate::clock::unix_time;
 use crate::pod_conn_svc::PodState;
+use crate::user::Identity;
 use shared::launch::*;
 
+/// Record of a launched trip, stored by database_svc in the trips table
+pub struct TripRecord {
+    // unix time in seconds the trip was launched at
+    pub time: u64,
+    // operator who launched the trip, as verified by auth_svc
+    pub username: String,
+    pub role: u8,
+    // session the launch was sent in
+    pub sid: String,
+    pub distance: f32,
+    pub max_speed: f32,
+}
+
 pub struct TripSvc {
     pub pod_state: Arc<Mutex<PodState>>,
 
-    pub rx_ctrl: Receiver<LaunchParams>,
+    // launch parameters and the operator who launched the trip
+    pub rx_ctrl: Receiver<(LaunchParams, Identity)>,
     pub tx_pod: Sender<u8>,
+    pub tx_data: Sender<TripRecord>,
 }
 
 impl TripSvc {
@@ -25,8 +42,26 @@ impl TripSvc {
         // send braking command to pod_conn when over
         // set timer for half for braking state
         // change state to locked
-        while let Some(params) = self.rx_ctrl.recv().await {
+        while let Some((params, operator)) = self.rx_ctrl.recv().await {
             let time = (params.distance.unwrap() / (params.max_speed.unwrap() / 3.6)) / 2.0;
+            println!(
+                "trip_svc: trip of {}m at up to {}km/h launched by {}",
+                params.distance.unwrap(),
+                params.max_speed.unwrap(),
+                operator.username
+            );
+
+            let record = TripRecord {
+                time: unix_time(),
+                username: operator.username.clone(),
+                role: operator.ugroup,
+                sid: operator.sid.clone(),
+                distance: params.distance.unwrap(),
+                max_speed: params.max_speed.unwrap(),
+            };
+            if let Err(e) = self.tx_data.send(record).await {
+                eprintln!("trip->database failed: {}", e);
+            }
 
             sleep(Duration::from_secs_f32(time)).await;
             *self.pod_state.lock().await = PodState::Braking;
@@ -36,6 +71,7 @@ impl TripSvc {
 
             sleep(Duration::from_secs_f32(time)).await;
             *self.pod_state.lock().await = PodState::Locked;
+            println!("trip_svc: trip launched by {} complete", operator.username);
         }
 
         println!("trip_svc: service down");
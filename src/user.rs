@@ -7,11 +7,13 @@ pub struct User {
     pub hash: String,
     pub ugroup: u8,
 }
-// ugroup = permissions
+// ugroup = id of the user's role, what each role may do is set by the policy in role.rs
 // 0 -> no permissions
-// 1 -> ???
-// 2 -> ???
+// 1 -> mission control
+// 2 -> software
+// 3 -> viewer
 // 255 -> admin
+// other ids are custom roles added at runtime
 
 impl User {
     pub fn new(name: String, pwd: String, ugroup: u8) -> Self {
@@ -29,3 +31,14 @@ impl User {
         Self { name, hash, ugroup }
     }
 }
+
+/// Verified sender of a command, taken by auth_svc from the claims of its token
+/// and passed along with every packet forwarded to another service
+/// Left empty for requests without a valid token and for those auth_svc makes itself
+#[derive(Clone, Debug, Default)]
+pub struct Identity {
+    pub username: String,
+    pub ugroup: u8,
+    // id of the session the command was sent in
+    pub sid: String,
+}